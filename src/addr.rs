@@ -0,0 +1,237 @@
+use crate::sev_info::SevInfoResp;
+use crate::GhcbMsrError;
+use core::fmt;
+use core::ops::{Add, AddAssign};
+
+/// Size of a guest page.
+pub const PAGE_SIZE: u64 = 4096;
+
+const PAGE_SHIFT: u32 = 12;
+
+/// Number of 4 KiB pages in a 2 MiB page.
+const PAGES_PER_2M: u64 = 512;
+
+/// A guest frame number, i.e. a guest physical address shifted right
+/// by the page size.
+#[derive(
+	Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+pub struct Gfn(u64);
+
+impl Gfn {
+	pub const fn new(gfn: u64) -> Self {
+		Self(gfn)
+	}
+
+	/// The raw value of the GFN.
+	pub const fn as_u64(&self) -> u64 {
+		self.0
+	}
+
+	/// The address of the start of the page, or `None` if it does
+	/// not fit in 64 bits.
+	pub const fn gpa(&self) -> Option<Gpa> {
+		if self.0 >> (64 - PAGE_SHIFT) != 0 {
+			return None;
+		}
+		Some(Gpa(self.0 << PAGE_SHIFT))
+	}
+
+	/// Whether the page is the first of a 2 MiB page.
+	pub const fn is_2m_aligned(&self) -> bool {
+		self.0.is_multiple_of(PAGES_PER_2M)
+	}
+}
+
+impl Add<u64> for Gfn {
+	type Output = Self;
+	fn add(self, pages: u64) -> Self {
+		Self(self.0 + pages)
+	}
+}
+
+impl AddAssign<u64> for Gfn {
+	fn add_assign(&mut self, pages: u64) {
+		self.0 += pages;
+	}
+}
+
+impl From<u64> for Gfn {
+	fn from(gfn: u64) -> Self {
+		Self(gfn)
+	}
+}
+
+impl From<Gfn> for u64 {
+	fn from(gfn: Gfn) -> Self {
+		gfn.0
+	}
+}
+
+impl fmt::Display for Gfn {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:#x}", self.0)
+	}
+}
+
+/// A guest physical address.
+#[derive(
+	Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+pub struct Gpa(u64);
+
+impl Gpa {
+	pub const fn new(gpa: u64) -> Self {
+		Self(gpa)
+	}
+
+	/// The raw value of the address.
+	pub const fn as_u64(&self) -> u64 {
+		self.0
+	}
+
+	/// The GFN of the page containing the address.
+	pub const fn gfn(&self) -> Gfn {
+		Gfn(self.0 >> PAGE_SHIFT)
+	}
+
+	/// The offset of the address within its page.
+	pub const fn page_offset(&self) -> u64 {
+		self.0 % PAGE_SIZE
+	}
+
+	pub const fn is_page_aligned(&self) -> bool {
+		self.page_offset() == 0
+	}
+
+	/// The address of the start of the page containing the address.
+	pub const fn align_down(&self) -> Self {
+		Self(self.0 - self.page_offset())
+	}
+}
+
+impl Add<u64> for Gpa {
+	type Output = Self;
+	fn add(self, bytes: u64) -> Self {
+		Self(self.0 + bytes)
+	}
+}
+
+impl AddAssign<u64> for Gpa {
+	fn add_assign(&mut self, bytes: u64) {
+		self.0 += bytes;
+	}
+}
+
+impl From<u64> for Gpa {
+	fn from(gpa: u64) -> Self {
+		Self(gpa)
+	}
+}
+
+impl From<Gpa> for u64 {
+	fn from(gpa: Gpa) -> Self {
+		gpa.0
+	}
+}
+
+impl TryFrom<Gfn> for Gpa {
+	type Error = GhcbMsrError;
+	/// Fails if the address of the page does not fit in 64 bits.
+	fn try_from(gfn: Gfn) -> Result<Self, Self::Error> {
+		match gfn.gpa() {
+			Some(gpa) => Ok(gpa),
+			None => Err(GhcbMsrError::InvalidGfn(gfn.0)),
+		}
+	}
+}
+
+impl TryFrom<Gpa> for Gfn {
+	type Error = GhcbMsrError;
+	/// Fails if the address is not page-aligned.
+	fn try_from(gpa: Gpa) -> Result<Self, Self::Error> {
+		if !gpa.is_page_aligned() {
+			return Err(GhcbMsrError::InvalidGpa(gpa.0));
+		}
+		Ok(gpa.gfn())
+	}
+}
+
+impl fmt::Display for Gpa {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:#x}", self.0)
+	}
+}
+
+/// The guest physical address space: its width and the position of
+/// the encryption bit (C-bit), which is not part of the address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddrSpace {
+	width: u8,
+	enc_bit_no: u8,
+}
+
+impl AddrSpace {
+	pub const fn new(width: u8, enc_bit_no: u8) -> Self {
+		Self { width, enc_bit_no }
+	}
+
+	/// The address space described by the hypervisor, for a guest
+	/// with the given physical address width (e.g. from CPUID
+	/// function `0x8000_0008`).
+	pub const fn from_sev_info(
+		resp: &SevInfoResp,
+		width: u8,
+	) -> Self {
+		Self::new(width, resp.enc_bit_no)
+	}
+
+	/// The physical address width.
+	pub const fn width(&self) -> u8 {
+		self.width
+	}
+
+	/// The position of the C-bit.
+	pub const fn enc_bit_no(&self) -> u8 {
+		self.enc_bit_no
+	}
+
+	/// The mask of the C-bit in an address.
+	pub const fn enc_mask(&self) -> u64 {
+		match 1u64.checked_shl(self.enc_bit_no as u32) {
+			Some(mask) => mask,
+			None => 0,
+		}
+	}
+
+	/// Check that `gpa` is within the address width and does not have
+	/// the C-bit set.
+	pub const fn check_gpa(
+		&self,
+		gpa: Gpa,
+	) -> Result<Gpa, GhcbMsrError> {
+		let width_ok = match 1u64.checked_shl(self.width as u32) {
+			Some(limit) => gpa.0 < limit,
+			None => true,
+		};
+		if !width_ok || gpa.0 & self.enc_mask() != 0 {
+			return Err(GhcbMsrError::InvalidGpa(gpa.0));
+		}
+		Ok(gpa)
+	}
+
+	/// Check that the page at `gfn` is within the address width and
+	/// its address does not have the C-bit set.
+	pub const fn check_gfn(
+		&self,
+		gfn: Gfn,
+	) -> Result<Gfn, GhcbMsrError> {
+		let Some(gpa) = gfn.gpa() else {
+			return Err(GhcbMsrError::InvalidGfn(gfn.0));
+		};
+		match self.check_gpa(gpa) {
+			Ok(_) => Ok(gfn),
+			Err(_) => Err(GhcbMsrError::InvalidGfn(gfn.0)),
+		}
+	}
+}
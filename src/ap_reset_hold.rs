@@ -1,10 +1,11 @@
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
 
 /// A request from the guest for the AP be placed in a HLT loop
 /// awaiting an INIT-SIPI-SIPI request.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct ApResetHoldReq {
 	data: u64,
 }
@@ -15,6 +16,21 @@ impl ApResetHoldReq {
 	}
 }
 
+impl TryFrom<u64> for ApResetHoldReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::AP_RESET_HOLD_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data != 0 {
+			return Err(GhcbMsrError::InvalidData);
+		}
+		Ok(Self::new())
+	}
+}
+
 impl GhcbMsrRequest for ApResetHoldReq {
 	type Resp = ApResetHoldResp;
 	fn data(&self) -> u64 {
@@ -27,8 +43,15 @@ impl GhcbMsrRequest for ApResetHoldReq {
 
 /// A response from the hypervisor after an INIT-SIPI-SIPI sequence
 /// has been received for the targeted AP to take it out of HLT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct ApResetHoldResp {}
 
+impl ApResetHoldResp {
+	pub const fn new() -> Self {
+		Self {}
+	}
+}
+
 impl TryFrom<u64> for ApResetHoldResp {
 	type Error = GhcbMsrError;
 	fn try_from(resp: u64) -> Result<Self, Self::Error> {
@@ -45,3 +68,14 @@ impl TryFrom<u64> for ApResetHoldResp {
 }
 
 impl GhcbMsrResp for ApResetHoldResp {}
+
+impl GhcbMsrRespEncode for ApResetHoldResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::AP_RESET_HOLD_RESP
+	}
+	fn data(&self) -> u64 {
+		// Any non-zero value signals that the INIT-SIPI-SIPI
+		// sequence was received.
+		1
+	}
+}
@@ -1,6 +1,12 @@
+use crate::addr::Gpa;
+use crate::ghcb::{Ghcb, GhcbError, GhcbField, GhcbRequest};
+use crate::nae::SwExitCode;
+use crate::transport::{exchange, GhcbMsrTransport};
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
+use core::ops::RangeInclusive;
 
 /// Requested register value for a [`CpuidReq`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,14 +18,15 @@ pub enum CpuidReg {
 	EDX = 3,
 }
 
-impl From<u8> for CpuidReg {
-	fn from(val: u8) -> Self {
+impl TryFrom<u8> for CpuidReg {
+	type Error = GhcbMsrError;
+	fn try_from(val: u8) -> Result<Self, Self::Error> {
 		match val {
-			_ if val == Self::EAX as u8 => Self::EAX,
-			_ if val == Self::EBX as u8 => Self::EBX,
-			_ if val == Self::ECX as u8 => Self::ECX,
-			_ if val == Self::EDX as u8 => Self::EDX,
-			_ => unreachable!(),
+			v if v == Self::EAX as u8 => Ok(Self::EAX),
+			v if v == Self::EBX as u8 => Ok(Self::EBX),
+			v if v == Self::ECX as u8 => Ok(Self::ECX),
+			v if v == Self::EDX as u8 => Ok(Self::EDX),
+			_ => Err(GhcbMsrError::InvalidData),
 		}
 	}
 }
@@ -29,38 +36,85 @@ impl From<u8> for CpuidReg {
 /// can be set up. Only one register can be obtained at a time.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CpuidReq {
-	data: u64,
+	function: u32,
+	reg: CpuidReg,
 }
 
 impl CpuidReq {
 	pub const fn new(cpuid_function: u32, reg: CpuidReg) -> Self {
-		let func = cpuid_function as u64;
-		let reg = reg as u64;
 		Self {
-			data: (func << 20) | (reg << 18),
+			function: cpuid_function,
+			reg,
 		}
 	}
+
+	/// The requested CPUID function.
+	pub const fn function(&self) -> u32 {
+		self.function
+	}
+
+	/// The requested register.
+	pub const fn reg(&self) -> CpuidReg {
+		self.reg
+	}
+}
+
+impl TryFrom<u64> for CpuidReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::CPUID_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data & 0x3ffff != 0 {
+			return Err(GhcbMsrError::InvalidData);
+		}
+		let function = (data >> 20) as u32;
+		let reg = CpuidReg::try_from(((data >> 18) & 0b11) as u8)?;
+		Ok(Self::new(function, reg))
+	}
 }
 
 impl GhcbMsrRequest for CpuidReq {
 	type Resp = CpuidResp;
 	fn data(&self) -> u64 {
-		self.data
+		let func = self.function as u64;
+		let reg = self.reg as u64;
+		(func << 20) | (reg << 18)
 	}
 	fn info(&self) -> GhcbMsrInfo {
 		GhcbMsrInfo::CPUID_REQ
 	}
+	fn response(
+		&self,
+		resp: u64,
+	) -> Result<Self::Resp, GhcbMsrError> {
+		let resp = Self::Resp::try_from(resp)?;
+		if resp.reg != self.reg {
+			return Err(GhcbMsrError::MismatchedData(
+				resp.reg as u64,
+			));
+		}
+		Ok(resp)
+	}
 }
 
 /// A response from the hypervisor to a CPUID request.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CpuidResp {
-	/// CPUID function register value
-	pub function: u32,
 	/// Returned CPUID register value
+	pub value: u32,
+	/// The register the value belongs to
 	pub reg: CpuidReg,
 }
 
+impl CpuidResp {
+	pub const fn new(value: u32, reg: CpuidReg) -> Self {
+		Self { value, reg }
+	}
+}
+
 impl TryFrom<u64> for CpuidResp {
 	type Error = GhcbMsrError;
 	fn try_from(resp: u64) -> Result<Self, Self::Error> {
@@ -69,13 +123,143 @@ impl TryFrom<u64> for CpuidResp {
 		if info != GhcbMsrInfo::CPUID_RESP {
 			return Err(GhcbMsrError::MismatchedInfo);
 		}
-		if data & 0xff != 0 {
+		if data & 0x3ffff != 0 {
 			return Err(GhcbMsrError::InvalidData);
 		}
-		let function = ((data >> 20) & 0xffffffff) as u32;
-		let reg = CpuidReg::from(((data >> 18) & 0b11) as u8);
-		Ok(Self { function, reg })
+		let value = ((data >> 20) & 0xffffffff) as u32;
+		let reg = CpuidReg::try_from(((data >> 18) & 0b11) as u8)?;
+		Ok(Self { value, reg })
 	}
 }
 
 impl GhcbMsrResp for CpuidResp {}
+
+impl GhcbMsrRespEncode for CpuidResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::CPUID_RESP
+	}
+	fn data(&self) -> u64 {
+		((self.value as u64) << 20) | ((self.reg as u64) << 18)
+	}
+}
+
+/// The four register values returned by a CPUID function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuidLeaf {
+	pub eax: u32,
+	pub ebx: u32,
+	pub ecx: u32,
+	pub edx: u32,
+}
+
+impl CpuidLeaf {
+	/// Retrieve all four registers of a CPUID function, issuing one
+	/// [`CpuidReq`] per register.
+	pub fn fetch<T: GhcbMsrTransport + ?Sized>(
+		transport: &mut T,
+		function: u32,
+	) -> Result<Self, GhcbMsrError> {
+		let mut get = |reg| {
+			let req = CpuidReq::new(function, reg);
+			exchange(transport, &req).map(|resp| resp.value)
+		};
+		Ok(Self {
+			eax: get(CpuidReg::EAX)?,
+			ebx: get(CpuidReg::EBX)?,
+			ecx: get(CpuidReg::ECX)?,
+			edx: get(CpuidReg::EDX)?,
+		})
+	}
+
+	/// Retrieve an inclusive range of CPUID functions, e.g.
+	/// `0..=max_basic` or `0x80000000..=max_ext`, where the maximum
+	/// function is the one reported in `EAX` by the first function of
+	/// the range. See [`CpuidLeaves`].
+	pub fn fetch_range<T: GhcbMsrTransport + ?Sized>(
+		transport: &mut T,
+		functions: RangeInclusive<u32>,
+	) -> CpuidLeaves<'_, T> {
+		CpuidLeaves {
+			transport,
+			functions,
+		}
+	}
+}
+
+/// An iterator over a range of CPUID functions, yielding each
+/// function along with its register values. Created by
+/// [`CpuidLeaf::fetch_range()`].
+pub struct CpuidLeaves<'a, T: ?Sized> {
+	transport: &'a mut T,
+	functions: RangeInclusive<u32>,
+}
+
+impl<T: GhcbMsrTransport + ?Sized> Iterator for CpuidLeaves<'_, T> {
+	type Item = Result<(u32, CpuidLeaf), GhcbMsrError>;
+	fn next(&mut self) -> Option<Self::Item> {
+		let function = self.functions.next()?;
+		let leaf = CpuidLeaf::fetch(self.transport, function);
+		Some(leaf.map(|leaf| (function, leaf)))
+	}
+}
+
+/// A CPUID request performed through the GHCB page. Unlike
+/// [`CpuidReq`], it retrieves all four registers at once and can
+/// pass the subfunction (`ECX`), `XCR0` and `XSS`, which some
+/// functions (e.g. `0xD`) depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuidGhcbReq {
+	function: u32,
+	index: u32,
+	xcr0: Option<u64>,
+	xss: Option<u64>,
+}
+
+impl CpuidGhcbReq {
+	pub const fn new(function: u32, index: u32) -> Self {
+		Self {
+			function,
+			index,
+			xcr0: None,
+			xss: None,
+		}
+	}
+
+	/// Pass the current value of `XCR0`. Required if `CR4.OSXSAVE`
+	/// is set.
+	pub const fn with_xcr0(mut self, xcr0: u64) -> Self {
+		self.xcr0 = Some(xcr0);
+		self
+	}
+
+	/// Pass the current value of `XSS`.
+	pub const fn with_xss(mut self, xss: u64) -> Self {
+		self.xss = Some(xss);
+		self
+	}
+}
+
+impl GhcbRequest for CpuidGhcbReq {
+	type Resp = CpuidLeaf;
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::CPUID
+	}
+	fn prepare(&self, ghcb: &mut Ghcb, _ghcb_gpa: Gpa) {
+		ghcb.set_rax(self.function as u64);
+		ghcb.set_rcx(self.index as u64);
+		if let Some(xcr0) = self.xcr0 {
+			ghcb.set_xcr0(xcr0);
+		}
+		if let Some(xss) = self.xss {
+			ghcb.set_xss(xss);
+		}
+	}
+	fn response(&self, ghcb: &Ghcb) -> Result<Self::Resp, GhcbError> {
+		Ok(CpuidLeaf {
+			eax: ghcb.get_valid(GhcbField::Rax)? as u32,
+			ebx: ghcb.get_valid(GhcbField::Rbx)? as u32,
+			ecx: ghcb.get_valid(GhcbField::Rcx)? as u32,
+			edx: ghcb.get_valid(GhcbField::Rdx)? as u32,
+		})
+	}
+}
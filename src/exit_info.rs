@@ -0,0 +1,155 @@
+use crate::ghcb::GhcbError;
+
+const EVENT_ERROR_VALID: u64 = 1 << 11;
+const EVENT_VALID: u64 = 1 << 31;
+
+/// Type of an event to inject, as encoded in the `EVENTINJ` format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventType {
+	ExternalInterrupt = 0,
+	Nmi = 2,
+	Exception = 3,
+	SoftwareInterrupt = 4,
+}
+
+impl TryFrom<u8> for EventType {
+	type Error = GhcbError;
+	fn try_from(val: u8) -> Result<Self, Self::Error> {
+		match val {
+			v if v == Self::ExternalInterrupt as u8 => {
+				Ok(Self::ExternalInterrupt)
+			}
+			v if v == Self::Nmi as u8 => Ok(Self::Nmi),
+			v if v == Self::Exception as u8 => Ok(Self::Exception),
+			v if v == Self::SoftwareInterrupt as u8 => {
+				Ok(Self::SoftwareInterrupt)
+			}
+			_ => Err(GhcbError::InvalidExitInfo),
+		}
+	}
+}
+
+/// An event the hypervisor asks the guest to inject instead of
+/// completing a non-automatic exit event, returned in
+/// `SW_EXITINFO2` in the `EVENTINJ` format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventInjection {
+	pub vector: u8,
+	pub event_type: EventType,
+	pub error_code: Option<u32>,
+}
+
+impl EventInjection {
+	/// An event without an error code.
+	pub const fn new(vector: u8, event_type: EventType) -> Self {
+		Self {
+			vector,
+			event_type,
+			error_code: None,
+		}
+	}
+
+	pub const fn with_error_code(mut self, error_code: u32) -> Self {
+		self.error_code = Some(error_code);
+		self
+	}
+
+	/// The `EVENTINJ` value for the event.
+	pub const fn encode(&self) -> u64 {
+		let mut val = EVENT_VALID
+			| ((self.event_type as u64) << 8)
+			| self.vector as u64;
+		if let Some(error_code) = self.error_code {
+			val |= EVENT_ERROR_VALID | ((error_code as u64) << 32);
+		}
+		val
+	}
+}
+
+impl TryFrom<u64> for EventInjection {
+	type Error = GhcbError;
+	fn try_from(val: u64) -> Result<Self, Self::Error> {
+		if val & EVENT_VALID == 0 {
+			return Err(GhcbError::InvalidExitInfo);
+		}
+		let event_type =
+			EventType::try_from((val >> 8) as u8 & 0b111)?;
+		let error_code = (val & EVENT_ERROR_VALID != 0)
+			.then_some((val >> 32) as u32);
+		Ok(Self {
+			vector: val as u8,
+			event_type,
+			error_code,
+		})
+	}
+}
+
+/// Reason for a GHCB-specific error, returned by the hypervisor in
+/// `SW_EXITINFO2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GhcbErrorReason {
+	/// The GHCB GPA is not registered.
+	NotRegistered,
+	/// Invalid GHCB usage or protocol version.
+	InvalidUsage,
+	/// `SW_SCRATCH` does not point to a valid area.
+	InvalidScratchArea,
+	/// A required input field is not marked as valid.
+	MissingInput,
+	/// An input field has an invalid value.
+	InvalidInput,
+	/// The `SW_EXITCODE` is not supported.
+	InvalidEvent,
+	/// A reason not defined by the specification.
+	Unknown(u64),
+}
+
+impl GhcbErrorReason {
+	/// The raw value of the reason.
+	pub const fn code(&self) -> u64 {
+		match self {
+			Self::NotRegistered => 1,
+			Self::InvalidUsage => 2,
+			Self::InvalidScratchArea => 3,
+			Self::MissingInput => 4,
+			Self::InvalidInput => 5,
+			Self::InvalidEvent => 6,
+			Self::Unknown(code) => *code,
+		}
+	}
+}
+
+impl From<u64> for GhcbErrorReason {
+	fn from(code: u64) -> Self {
+		match code {
+			1 => Self::NotRegistered,
+			2 => Self::InvalidUsage,
+			3 => Self::InvalidScratchArea,
+			4 => Self::MissingInput,
+			5 => Self::InvalidInput,
+			6 => Self::InvalidEvent,
+			code => Self::Unknown(code),
+		}
+	}
+}
+
+/// Decode the outcome of a non-automatic exit event from the
+/// `SW_EXITINFO1` and `SW_EXITINFO2` values returned by the
+/// hypervisor. Only the lower 32 bits of `SW_EXITINFO1` are
+/// considered, as the upper half may hold event-specific data.
+pub fn check_exit_info(
+	info_1: u64,
+	info_2: u64,
+) -> Result<(), GhcbError> {
+	match info_1 & 0xffffffff {
+		0 => Ok(()),
+		1 => Err(GhcbError::EventInjection(
+			EventInjection::try_from(info_2)?,
+		)),
+		2 => {
+			Err(GhcbError::Hypervisor(GhcbErrorReason::from(info_2)))
+		}
+		_ => Err(GhcbError::InvalidExitInfo),
+	}
+}
@@ -1,10 +1,147 @@
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
+use core::fmt;
+use core::ops::BitOr;
+
+/// The hypervisor feature bitmap returned in a
+/// [`FeatureSupportResp`]. Bits unknown to this crate are preserved.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct HvFeatures(u64);
+
+impl HvFeatures {
+	/// SEV-SNP is supported.
+	pub const SEV_SNP: Self = Self(1 << 0);
+	/// SEV-SNP AP creation is supported.
+	pub const SNP_AP_CREATION: Self = Self(1 << 1);
+	/// SEV-SNP restricted injection is supported.
+	pub const SNP_RESTRICTED_INJECTION: Self = Self(1 << 2);
+	/// SEV-SNP restricted injection timer is supported.
+	pub const SNP_RESTRICTED_INJECTION_TIMER: Self = Self(1 << 3);
+	/// The APIC ID list request is supported.
+	pub const APIC_ID_LIST: Self = Self(1 << 4);
+	/// SEV-SNP multiple VMPL levels are supported.
+	pub const SNP_MULTI_VMPL: Self = Self(1 << 5);
+
+	const NAMES: [(Self, &'static str, &'static str); 6] = [
+		(Self::SEV_SNP, "SEV_SNP", "SEV-SNP"),
+		(Self::SNP_AP_CREATION, "SNP_AP_CREATION", "AP creation"),
+		(
+			Self::SNP_RESTRICTED_INJECTION,
+			"SNP_RESTRICTED_INJECTION",
+			"restricted injection",
+		),
+		(
+			Self::SNP_RESTRICTED_INJECTION_TIMER,
+			"SNP_RESTRICTED_INJECTION_TIMER",
+			"restricted injection timer",
+		),
+		(Self::APIC_ID_LIST, "APIC_ID_LIST", "APIC ID list"),
+		(Self::SNP_MULTI_VMPL, "SNP_MULTI_VMPL", "multi-VMPL"),
+	];
+
+	/// A bitmap with no features.
+	pub const fn empty() -> Self {
+		Self(0)
+	}
+
+	/// Create a bitmap from its raw value, keeping unknown bits.
+	pub const fn from_bits(bits: u64) -> Self {
+		Self(bits)
+	}
+
+	/// The raw value of the bitmap.
+	pub const fn bits(&self) -> u64 {
+		self.0
+	}
+
+	/// Whether all the features in `other` are present.
+	pub const fn contains(&self, other: Self) -> bool {
+		self.0 & other.0 == other.0
+	}
+
+	/// Check that all the features in `required` are present,
+	/// returning the missing ones otherwise.
+	pub const fn require(
+		&self,
+		required: Self,
+	) -> Result<(), GhcbMsrError> {
+		let missing = required.0 & !self.0;
+		if missing != 0 {
+			return Err(GhcbMsrError::MissingFeatures(missing));
+		}
+		Ok(())
+	}
+
+	/// The bits in the bitmap not known to this crate.
+	pub const fn unknown(&self) -> u64 {
+		let mut known = 0;
+		let mut i = 0;
+		while i < Self::NAMES.len() {
+			known |= Self::NAMES[i].0 .0;
+			i += 1;
+		}
+		self.0 & !known
+	}
+
+	fn fmt_with(
+		&self,
+		f: &mut fmt::Formatter<'_>,
+		pretty: bool,
+		sep: &str,
+	) -> fmt::Result {
+		let mut first = true;
+		for (feat, name, pretty_name) in Self::NAMES {
+			if !self.contains(feat) {
+				continue;
+			}
+			if !first {
+				f.write_str(sep)?;
+			}
+			f.write_str(if pretty { pretty_name } else { name })?;
+			first = false;
+		}
+		if self.unknown() != 0 {
+			if !first {
+				f.write_str(sep)?;
+			}
+			write!(f, "{:#x}", self.unknown())?;
+			first = false;
+		}
+		if first {
+			f.write_str(if pretty { "none" } else { "empty" })?;
+		}
+		Ok(())
+	}
+}
+
+impl BitOr for HvFeatures {
+	type Output = Self;
+	fn bitor(self, rhs: Self) -> Self {
+		Self(self.0 | rhs.0)
+	}
+}
+
+impl fmt::Debug for HvFeatures {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("HvFeatures(")?;
+		self.fmt_with(f, false, " | ")?;
+		f.write_str(")")
+	}
+}
+
+/// Lists the enabled features in a human readable form, e.g.
+/// `SEV-SNP, AP creation`.
+impl fmt::Display for HvFeatures {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.fmt_with(f, true, ", ")
+	}
+}
 
 /// A request from the guest to retrieve the hypervisor's feature
 /// bitmap.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct FeatureSupportReq {
 	data: u64,
 }
@@ -15,6 +152,21 @@ impl FeatureSupportReq {
 	}
 }
 
+impl TryFrom<u64> for FeatureSupportReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::FEAT_SUPPORT_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data != 0 {
+			return Err(GhcbMsrError::InvalidData);
+		}
+		Ok(Self::new())
+	}
+}
+
 impl GhcbMsrRequest for FeatureSupportReq {
 	type Resp = FeatureSupportResp;
 	fn data(&self) -> u64 {
@@ -26,8 +178,15 @@ impl GhcbMsrRequest for FeatureSupportReq {
 }
 
 /// A response from the hypervisor containing its feature bitmap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FeatureSupportResp {
-	pub features: u64,
+	pub features: HvFeatures,
+}
+
+impl FeatureSupportResp {
+	pub const fn new(features: HvFeatures) -> Self {
+		Self { features }
+	}
 }
 
 impl TryFrom<u64> for FeatureSupportResp {
@@ -38,8 +197,19 @@ impl TryFrom<u64> for FeatureSupportResp {
 		if info != GhcbMsrInfo::FEAT_SUPPORT_RESP {
 			return Err(GhcbMsrError::MismatchedInfo);
 		}
-		Ok(Self { features: data })
+		Ok(Self {
+			features: HvFeatures::from_bits(data),
+		})
 	}
 }
 
 impl GhcbMsrResp for FeatureSupportResp {}
+
+impl GhcbMsrRespEncode for FeatureSupportResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::FEAT_SUPPORT_RESP
+	}
+	fn data(&self) -> u64 {
+		self.features.bits()
+	}
+}
@@ -0,0 +1,384 @@
+use crate::addr::Gpa;
+use crate::exit_info::{EventInjection, GhcbErrorReason};
+use crate::nae::SwExitCode;
+use core::fmt;
+
+/// Size of the GHCB page.
+pub const GHCB_SIZE: usize = 4096;
+
+/// Offset of the shared buffer within the GHCB page.
+pub const SHARED_BUFFER_OFFSET: usize = 0x800;
+
+/// Size of the shared buffer.
+pub const SHARED_BUFFER_SIZE: usize = 0x7f0;
+
+const VALID_BITMAP_OFFSET: usize = 0x3f0;
+const VALID_BITMAP_SIZE: usize = 16;
+const PROTOCOL_VERSION_OFFSET: usize = 0xffa;
+const USAGE_OFFSET: usize = 0xffc;
+
+/// Fields in the save area of the GHCB. The discriminant is the
+/// offset of the field within the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum GhcbField {
+	Cpl = 0x0cb,
+	Xss = 0x140,
+	Dr7 = 0x160,
+	Rip = 0x178,
+	Rsp = 0x1d8,
+	Rax = 0x1f8,
+	Rcx = 0x308,
+	Rdx = 0x310,
+	Rbx = 0x318,
+	Rbp = 0x328,
+	Rsi = 0x330,
+	Rdi = 0x338,
+	R8 = 0x340,
+	R9 = 0x348,
+	R10 = 0x350,
+	R11 = 0x358,
+	R12 = 0x360,
+	R13 = 0x368,
+	R14 = 0x370,
+	R15 = 0x378,
+	SwExitCode = 0x390,
+	SwExitInfo1 = 0x398,
+	SwExitInfo2 = 0x3a0,
+	SwScratch = 0x3a8,
+	Xcr0 = 0x3e8,
+	X87StateGpa = 0x400,
+}
+
+impl GhcbField {
+	/// The offset of the field within the GHCB page.
+	pub const fn offset(&self) -> usize {
+		*self as usize
+	}
+
+	/// The size of the field in bytes.
+	pub const fn size(&self) -> usize {
+		match self {
+			Self::Cpl => 1,
+			_ => 8,
+		}
+	}
+
+	/// The index of the field's bit in the valid bitmap, or `None`
+	/// for fields located past the range covered by the bitmap.
+	pub const fn valid_bit(&self) -> Option<usize> {
+		let bit = self.offset() / 8;
+		if bit < VALID_BITMAP_SIZE * 8 {
+			Some(bit)
+		} else {
+			None
+		}
+	}
+}
+
+/// Potential errors encountered when using the GHCB page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GhcbError {
+	/// The hypervisor did not mark a required output field as
+	/// valid.
+	FieldNotValid(GhcbField),
+	/// Unknown `SW_EXITCODE` value (see
+	/// [`SwExitCode`]).
+	InvalidExitCode(u64),
+	/// `SW_EXITINFO1` or `SW_EXITINFO2` has an invalid format.
+	InvalidExitInfo,
+	/// The hypervisor changed `SW_SCRATCH`. Contains the returned
+	/// value.
+	ScratchChanged(u64),
+	/// The length of a buffer passed through the GHCB is invalid.
+	InvalidLength(usize),
+	/// The hypervisor returned an inconsistent page state change
+	/// buffer.
+	InvalidPscBuffer,
+	/// The hypervisor failed to process an entry of a page state
+	/// change. Contains the index of the entry and `SW_EXITINFO2`.
+	PageStateChange(u16, u64),
+	/// A request field does not fit in its encoding. Contains the
+	/// offending value.
+	OutOfRange(u64),
+	/// The hypervisor asked the guest to inject an event instead of
+	/// completing the request.
+	EventInjection(EventInjection),
+	/// The hypervisor signaled a GHCB-specific error.
+	Hypervisor(GhcbErrorReason),
+}
+
+/// Trait implemented by all requests performed through the GHCB
+/// page, i.e. non-automatic exit events.
+pub trait GhcbRequest {
+	type Resp;
+	/// The `SW_EXITCODE` of the event.
+	fn exit_code(&self) -> SwExitCode;
+	/// The `SW_EXITINFO1` of the event.
+	fn exit_info_1(&self) -> u64 {
+		0
+	}
+	/// The `SW_EXITINFO2` of the event.
+	fn exit_info_2(&self) -> u64 {
+		0
+	}
+	/// Fill in the event-specific inputs in the GHCB, located at
+	/// `ghcb_gpa`.
+	fn prepare(&self, _ghcb: &mut Ghcb, _ghcb_gpa: Gpa) {}
+	/// Parse the outputs of the event after the hypervisor returns
+	/// successfully.
+	fn response(&self, ghcb: &Ghcb) -> Result<Self::Resp, GhcbError>;
+}
+
+/// The GHCB page, shared between the guest and the hypervisor.
+///
+/// The layout follows the GHCB specification: a save area, a shared
+/// buffer at [`SHARED_BUFFER_OFFSET`], and the protocol version and
+/// usage fields at the end of the page. The type is page-aligned, so
+/// that it can be placed directly in the shared page by the user.
+#[derive(Clone, PartialEq, Eq)]
+#[repr(C, align(4096))]
+pub struct Ghcb {
+	page: [u8; GHCB_SIZE],
+}
+
+impl Ghcb {
+	/// A zeroed GHCB page.
+	pub const fn new() -> Self {
+		Self {
+			page: [0; GHCB_SIZE],
+		}
+	}
+
+	pub const fn from_bytes(page: [u8; GHCB_SIZE]) -> Self {
+		Self { page }
+	}
+
+	/// The raw contents of the page.
+	pub fn as_bytes(&self) -> &[u8; GHCB_SIZE] {
+		&self.page
+	}
+
+	/// The raw contents of the page, mutably.
+	pub fn as_bytes_mut(&mut self) -> &mut [u8; GHCB_SIZE] {
+		&mut self.page
+	}
+
+	/// Reset the save area and the valid bitmap between events. The
+	/// shared buffer, protocol version and usage are preserved.
+	pub fn clear(&mut self) {
+		self.page[..SHARED_BUFFER_OFFSET].fill(0);
+	}
+
+	/// Whether a save area field is marked as valid. Fields without
+	/// a valid bit are never reported as valid.
+	pub fn is_valid(&self, field: GhcbField) -> bool {
+		match field.valid_bit() {
+			Some(bit) => {
+				self.page[VALID_BITMAP_OFFSET + bit / 8]
+					& (1 << (bit % 8))
+					!= 0
+			}
+			None => false,
+		}
+	}
+
+	/// Read a save area field, regardless of whether it is marked as
+	/// valid.
+	pub fn get(&self, field: GhcbField) -> u64 {
+		let off = field.offset();
+		let mut val = [0u8; 8];
+		val[..field.size()]
+			.copy_from_slice(&self.page[off..off + field.size()]);
+		u64::from_le_bytes(val)
+	}
+
+	/// Read a save area field, failing if it is not marked as
+	/// valid. Used to check the outputs of the hypervisor.
+	pub fn get_valid(
+		&self,
+		field: GhcbField,
+	) -> Result<u64, GhcbError> {
+		if !self.is_valid(field) {
+			return Err(GhcbError::FieldNotValid(field));
+		}
+		Ok(self.get(field))
+	}
+
+	/// Write a save area field and mark it as valid, if it has a
+	/// valid bit. Values wider than the field are truncated.
+	pub fn set(&mut self, field: GhcbField, val: u64) {
+		let off = field.offset();
+		let val = val.to_le_bytes();
+		self.page[off..off + field.size()]
+			.copy_from_slice(&val[..field.size()]);
+		if let Some(bit) = field.valid_bit() {
+			self.page[VALID_BITMAP_OFFSET + bit / 8] |=
+				1 << (bit % 8);
+		}
+	}
+
+	/// The current privilege level.
+	pub fn cpl(&self) -> u8 {
+		self.get(GhcbField::Cpl) as u8
+	}
+
+	pub fn set_cpl(&mut self, cpl: u8) {
+		self.set(GhcbField::Cpl, cpl as u64)
+	}
+
+	pub fn rax(&self) -> u64 {
+		self.get(GhcbField::Rax)
+	}
+
+	pub fn set_rax(&mut self, val: u64) {
+		self.set(GhcbField::Rax, val)
+	}
+
+	pub fn rbx(&self) -> u64 {
+		self.get(GhcbField::Rbx)
+	}
+
+	pub fn set_rbx(&mut self, val: u64) {
+		self.set(GhcbField::Rbx, val)
+	}
+
+	pub fn rcx(&self) -> u64 {
+		self.get(GhcbField::Rcx)
+	}
+
+	pub fn set_rcx(&mut self, val: u64) {
+		self.set(GhcbField::Rcx, val)
+	}
+
+	pub fn rdx(&self) -> u64 {
+		self.get(GhcbField::Rdx)
+	}
+
+	pub fn set_rdx(&mut self, val: u64) {
+		self.set(GhcbField::Rdx, val)
+	}
+
+	pub fn xcr0(&self) -> u64 {
+		self.get(GhcbField::Xcr0)
+	}
+
+	pub fn set_xcr0(&mut self, val: u64) {
+		self.set(GhcbField::Xcr0, val)
+	}
+
+	pub fn xss(&self) -> u64 {
+		self.get(GhcbField::Xss)
+	}
+
+	pub fn set_xss(&mut self, val: u64) {
+		self.set(GhcbField::Xss, val)
+	}
+
+	/// The exit code of the non-automatic exit event.
+	pub fn sw_exit_code(&self) -> u64 {
+		self.get(GhcbField::SwExitCode)
+	}
+
+	pub fn set_sw_exit_code(&mut self, val: u64) {
+		self.set(GhcbField::SwExitCode, val)
+	}
+
+	/// Exit-specific information. On return from the hypervisor, its
+	/// lower 32 bits signal whether the event was successful.
+	pub fn sw_exit_info_1(&self) -> u64 {
+		self.get(GhcbField::SwExitInfo1)
+	}
+
+	pub fn set_sw_exit_info_1(&mut self, val: u64) {
+		self.set(GhcbField::SwExitInfo1, val)
+	}
+
+	/// Exit-specific information.
+	pub fn sw_exit_info_2(&self) -> u64 {
+		self.get(GhcbField::SwExitInfo2)
+	}
+
+	pub fn set_sw_exit_info_2(&mut self, val: u64) {
+		self.set(GhcbField::SwExitInfo2, val)
+	}
+
+	/// GPA of the scratch area used by some events, usually within
+	/// the shared buffer.
+	pub fn sw_scratch(&self) -> u64 {
+		self.get(GhcbField::SwScratch)
+	}
+
+	pub fn set_sw_scratch(&mut self, val: u64) {
+		self.set(GhcbField::SwScratch, val)
+	}
+
+	/// The raw bitmap of valid save area fields. Bit `n` covers the
+	/// 8-byte slot at offset `n * 8`.
+	pub fn valid_bitmap(&self) -> [u8; VALID_BITMAP_SIZE] {
+		let mut bitmap = [0u8; VALID_BITMAP_SIZE];
+		bitmap.copy_from_slice(
+			&self.page[VALID_BITMAP_OFFSET
+				..VALID_BITMAP_OFFSET + VALID_BITMAP_SIZE],
+		);
+		bitmap
+	}
+
+	/// The shared buffer.
+	pub fn shared_buffer(&self) -> &[u8] {
+		&self.page[SHARED_BUFFER_OFFSET
+			..SHARED_BUFFER_OFFSET + SHARED_BUFFER_SIZE]
+	}
+
+	/// The shared buffer, mutably.
+	pub fn shared_buffer_mut(&mut self) -> &mut [u8] {
+		&mut self.page[SHARED_BUFFER_OFFSET
+			..SHARED_BUFFER_OFFSET + SHARED_BUFFER_SIZE]
+	}
+
+	/// The GHCB protocol version in use.
+	pub fn protocol_version(&self) -> u16 {
+		let off = PROTOCOL_VERSION_OFFSET;
+		u16::from_le_bytes([self.page[off], self.page[off + 1]])
+	}
+
+	pub fn set_protocol_version(&mut self, version: u16) {
+		let off = PROTOCOL_VERSION_OFFSET;
+		self.page[off..off + 2]
+			.copy_from_slice(&version.to_le_bytes());
+	}
+
+	/// The GHCB usage. Zero indicates the standard layout.
+	pub fn usage(&self) -> u32 {
+		let mut val = [0u8; 4];
+		val.copy_from_slice(
+			&self.page[USAGE_OFFSET..USAGE_OFFSET + 4],
+		);
+		u32::from_le_bytes(val)
+	}
+
+	pub fn set_usage(&mut self, usage: u32) {
+		self.page[USAGE_OFFSET..USAGE_OFFSET + 4]
+			.copy_from_slice(&usage.to_le_bytes());
+	}
+}
+
+impl Default for Ghcb {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl fmt::Debug for Ghcb {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Ghcb")
+			.field("sw_exit_code", &self.sw_exit_code())
+			.field("sw_exit_info_1", &self.sw_exit_info_1())
+			.field("sw_exit_info_2", &self.sw_exit_info_2())
+			.field("sw_scratch", &self.sw_scratch())
+			.field("valid_bitmap", &self.valid_bitmap())
+			.field("protocol_version", &self.protocol_version())
+			.field("usage", &self.usage())
+			.finish_non_exhaustive()
+	}
+}
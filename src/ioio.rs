@@ -0,0 +1,318 @@
+use crate::addr::Gpa;
+use crate::ghcb::{Ghcb, GhcbError, GhcbField, GhcbRequest};
+use crate::nae::SwExitCode;
+use crate::transport::{exchange_ghcb, GhcbTransport};
+use core::fmt;
+
+/// Direction of a port I/O access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IoDirection {
+	Out = 0,
+	In = 1,
+}
+
+/// Size of the operand of a port I/O access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IoSize {
+	Byte = 1,
+	Word = 2,
+	Dword = 4,
+}
+
+impl IoSize {
+	const fn mask(&self) -> u64 {
+		match self {
+			Self::Byte => 0xff,
+			Self::Word => 0xffff,
+			Self::Dword => 0xffffffff,
+		}
+	}
+}
+
+/// Address size of a string port I/O access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IoAddrSize {
+	Addr16 = 1,
+	Addr32 = 2,
+	Addr64 = 4,
+}
+
+/// Segment register of a string port I/O access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IoSegment {
+	ES = 0,
+	CS = 1,
+	SS = 2,
+	DS = 3,
+	FS = 4,
+	GS = 5,
+}
+
+impl TryFrom<u8> for IoSegment {
+	type Error = GhcbError;
+	fn try_from(val: u8) -> Result<Self, Self::Error> {
+		match val {
+			v if v == Self::ES as u8 => Ok(Self::ES),
+			v if v == Self::CS as u8 => Ok(Self::CS),
+			v if v == Self::SS as u8 => Ok(Self::SS),
+			v if v == Self::DS as u8 => Ok(Self::DS),
+			v if v == Self::FS as u8 => Ok(Self::FS),
+			v if v == Self::GS as u8 => Ok(Self::GS),
+			_ => Err(GhcbError::InvalidExitInfo),
+		}
+	}
+}
+
+/// A port I/O access, encoded in `SW_EXITINFO1` for `IOIO` events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoioInfo {
+	port: u16,
+	dir: IoDirection,
+	size: IoSize,
+	addr_size: IoAddrSize,
+	segment: IoSegment,
+	string: bool,
+	rep: bool,
+}
+
+impl IoioInfo {
+	/// A non-string access to `port`.
+	pub const fn new(
+		port: u16,
+		dir: IoDirection,
+		size: IoSize,
+	) -> Self {
+		Self {
+			port,
+			dir,
+			size,
+			addr_size: IoAddrSize::Addr64,
+			segment: IoSegment::ES,
+			string: false,
+			rep: false,
+		}
+	}
+
+	/// Turn the access into a string access (`INS`/`OUTS`) with the
+	/// given segment and address size.
+	pub const fn with_string(
+		mut self,
+		segment: IoSegment,
+		addr_size: IoAddrSize,
+	) -> Self {
+		self.string = true;
+		self.segment = segment;
+		self.addr_size = addr_size;
+		self
+	}
+
+	/// Mark the access as having a `REP` prefix.
+	pub const fn with_rep(mut self) -> Self {
+		self.rep = true;
+		self
+	}
+
+	pub const fn port(&self) -> u16 {
+		self.port
+	}
+
+	pub const fn dir(&self) -> IoDirection {
+		self.dir
+	}
+
+	pub const fn size(&self) -> IoSize {
+		self.size
+	}
+
+	pub const fn addr_size(&self) -> IoAddrSize {
+		self.addr_size
+	}
+
+	pub const fn segment(&self) -> IoSegment {
+		self.segment
+	}
+
+	pub const fn is_string(&self) -> bool {
+		self.string
+	}
+
+	pub const fn is_rep(&self) -> bool {
+		self.rep
+	}
+
+	/// The `SW_EXITINFO1` value for the access.
+	pub const fn encode(&self) -> u64 {
+		((self.port as u64) << 16)
+			| ((self.segment as u64) << 10)
+			| ((self.addr_size as u64) << 7)
+			| ((self.size as u64) << 4)
+			| ((self.rep as u64) << 3)
+			| ((self.string as u64) << 2)
+			| self.dir as u64
+	}
+}
+
+impl TryFrom<u64> for IoioInfo {
+	type Error = GhcbError;
+	fn try_from(info: u64) -> Result<Self, Self::Error> {
+		if info & 0xffff_ffff_0000_e002 != 0 {
+			return Err(GhcbError::InvalidExitInfo);
+		}
+		let dir = if info & 1 != 0 {
+			IoDirection::In
+		} else {
+			IoDirection::Out
+		};
+		let size = match (info >> 4) & 0b111 {
+			1 => IoSize::Byte,
+			2 => IoSize::Word,
+			4 => IoSize::Dword,
+			_ => return Err(GhcbError::InvalidExitInfo),
+		};
+		let addr_size = match (info >> 7) & 0b111 {
+			1 => IoAddrSize::Addr16,
+			2 => IoAddrSize::Addr32,
+			4 => IoAddrSize::Addr64,
+			_ => return Err(GhcbError::InvalidExitInfo),
+		};
+		Ok(Self {
+			port: (info >> 16) as u16,
+			dir,
+			size,
+			addr_size,
+			segment: IoSegment::try_from(
+				((info >> 10) & 0b111) as u8,
+			)?,
+			string: info & (1 << 2) != 0,
+			rep: info & (1 << 3) != 0,
+		})
+	}
+}
+
+/// A request to write a value to an I/O port (`OUT`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoOutReq {
+	info: IoioInfo,
+	value: u32,
+}
+
+impl IoOutReq {
+	pub const fn new(port: u16, size: IoSize, value: u32) -> Self {
+		Self {
+			info: IoioInfo::new(port, IoDirection::Out, size),
+			value,
+		}
+	}
+}
+
+impl GhcbRequest for IoOutReq {
+	type Resp = ();
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::IOIO
+	}
+	fn exit_info_1(&self) -> u64 {
+		self.info.encode()
+	}
+	fn prepare(&self, ghcb: &mut Ghcb, _ghcb_gpa: Gpa) {
+		ghcb.set_rax(self.value as u64 & self.info.size.mask());
+	}
+	fn response(
+		&self,
+		_ghcb: &Ghcb,
+	) -> Result<Self::Resp, GhcbError> {
+		Ok(())
+	}
+}
+
+/// A request to read a value from an I/O port (`IN`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoInReq {
+	info: IoioInfo,
+}
+
+impl IoInReq {
+	pub const fn new(port: u16, size: IoSize) -> Self {
+		Self {
+			info: IoioInfo::new(port, IoDirection::In, size),
+		}
+	}
+}
+
+impl GhcbRequest for IoInReq {
+	type Resp = u32;
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::IOIO
+	}
+	fn exit_info_1(&self) -> u64 {
+		self.info.encode()
+	}
+	fn response(&self, ghcb: &Ghcb) -> Result<Self::Resp, GhcbError> {
+		let rax = ghcb.get_valid(GhcbField::Rax)?;
+		Ok((rax & self.info.size.mask()) as u32)
+	}
+}
+
+/// Base port of the first 16550 UART.
+pub const COM1: u16 = 0x3f8;
+
+/// Offset of the line status register from the UART base port.
+const UART_LSR: u16 = 5;
+
+/// Transmit holding register empty bit of the line status register.
+const UART_LSR_THRE: u32 = 1 << 5;
+
+/// Number of times the line status register is polled before
+/// writing a byte regardless.
+const UART_TX_RETRIES: usize = 1000;
+
+/// A minimal 16550 UART console writing to the transmit register
+/// through `IOIO` events, usable before any other device is set up.
+/// Line feeds written through [`fmt::Write`] are sent as `\r\n`.
+#[derive(Debug)]
+pub struct GhcbSerial<T> {
+	transport: T,
+	base: u16,
+}
+
+impl<T: GhcbTransport> GhcbSerial<T> {
+	/// A console on the UART at the given base port (e.g. [`COM1`]).
+	pub const fn new(transport: T, base: u16) -> Self {
+		Self { transport, base }
+	}
+
+	/// Write a single byte, once the transmit buffer is empty. If
+	/// the buffer does not empty after a bounded number of polls of
+	/// the line status register, the byte is written anyway.
+	pub fn write_byte(&mut self, byte: u8) -> Result<(), GhcbError> {
+		let lsr = IoInReq::new(self.base + UART_LSR, IoSize::Byte);
+		for _ in 0..UART_TX_RETRIES {
+			let status = exchange_ghcb(&mut self.transport, &lsr)?;
+			if status & UART_LSR_THRE != 0 {
+				break;
+			}
+		}
+		let req = IoOutReq::new(self.base, IoSize::Byte, byte as u32);
+		exchange_ghcb(&mut self.transport, &req)
+	}
+
+	/// Give up the console, returning the underlying transport.
+	pub fn into_transport(self) -> T {
+		self.transport
+	}
+}
+
+impl<T: GhcbTransport> fmt::Write for GhcbSerial<T> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for byte in s.bytes() {
+			if byte == b'\n' {
+				self.write_byte(b'\r').map_err(|_| fmt::Error)?;
+			}
+			self.write_byte(byte).map_err(|_| fmt::Error)?;
+		}
+		Ok(())
+	}
+}
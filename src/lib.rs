@@ -6,10 +6,18 @@
 //! [SEV-ES Guest-Hypervisor Communication Block](https://www.amd.com/system/files/TechDocs/56421-guest-hypervisor-communication-block-standardization.pdf)
 //! (GHCB) MSR protocol (section 2.3.1).
 //!
-//! The crate is only concerned with the creation of correct requests,
-//! and parsing and error-checking the responses from the hypervisor.
-//! Interacting with the MSRs and handling the responses are left to
-//! the user.
+//! The crate is mainly concerned with the creation of correct
+//! requests, and parsing and error-checking the responses from the
+//! hypervisor. Only the privileged instructions (accessing the GHCB
+//! MSR and `VMGEXIT`) are left to the user, through the
+//! [`GhcbMsrTransport`](transport::GhcbMsrTransport) trait; the
+//! crate can then drive full round-trips, enforce the protocol call
+//! order with [`GhcbSession`](session::GhcbSession), and perform
+//! non-automatic exit events through the GHCB page (see
+//! [`ghcb`]).
+//!
+//! Requests can also be decoded from raw MSR values, so the same
+//! types can be used on the hypervisor side of the protocol.
 //!
 //! The crate does not perform any allocations, does not depend on the
 //! standard Rust library and has no other external dependencies. It
@@ -54,6 +62,34 @@
 //!     Err(e) => println!("Error: {:?}", e),
 //! }
 //! ```
+//!
+//! ## Transport
+//!
+//! Alternatively, the user may implement the
+//! [`GhcbMsrTransport`](transport::GhcbMsrTransport) trait for their
+//! MSR accessors, and let [`transport::exchange()`] drive the whole
+//! round-trip. The previous value of the GHCB MSR is saved and
+//! restored around the call.
+//!
+//! ```no_run
+//! # struct Msr;
+//! # impl Msr {
+//! #     fn new() -> Self { Self }
+//! # }
+//! # impl ghcb_msr_protocol::transport::GhcbMsrTransport for Msr {
+//! #     fn write_msr(&mut self, val: u64) { }
+//! #     fn read_msr(&mut self) -> u64 { 0xdeadbeef }
+//! #     fn vmgexit(&mut self) { }
+//! # }
+//! use ghcb_msr_protocol::{
+//!     sev_info::SevInfoReq,
+//!     transport::exchange
+//! };
+//!
+//! let mut msr = Msr::new();
+//! let resp = exchange(&mut msr, &SevInfoReq::new()).unwrap();
+//! println!("Min. protocol version: {}", resp.min_ver);
+//! ```
 
 use core::fmt::Debug;
 
@@ -84,6 +120,43 @@ pub mod feature_support;
 /// Guest termination.
 pub mod termination;
 
+/// MSR access and request/response round-trips.
+pub mod transport;
+
+/// Protocol sessions enforcing the correct call order.
+pub mod session;
+
+/// GHCB page layout.
+pub mod ghcb;
+
+/// Non-automatic exit (NAE) events.
+pub mod nae;
+
+/// Port I/O events and serial console.
+pub mod ioio;
+
+/// MMIO events.
+pub mod mmio;
+
+/// MSR access events.
+pub mod msr;
+
+/// Decoding of the outcome of GHCB events.
+pub mod exit_info;
+
+/// Page state changes through the GHCB shared buffer.
+pub mod psc;
+
+/// Guest-side tracking of page states.
+pub mod page_tracker;
+
+/// Guest frame numbers and physical addresses.
+pub mod addr;
+
+/// Mock hypervisor for testing.
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
 /// Potential errors encountered when parsing the hypervisor's response.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GhcbMsrError {
@@ -97,11 +170,45 @@ pub enum GhcbMsrError {
 	/// happen if the registered GPA for
 	/// [`RegisterGhcbResp`](register_ghcb::RegisterGhcbResp)
 	/// does not match the one in the corresponding
-	/// [`RegisterGhcbReq`](register_ghcb::RegisterGhcbReq).
+	/// [`RegisterGhcbReq`](register_ghcb::RegisterGhcbReq), or if
+	/// the register in a [`CpuidResp`](cpuid::CpuidResp) is not the
+	/// requested one.
 	MismatchedData(u64),
 	/// The guest requested termination and the hypervisor did not
 	/// comply.
 	ShouldNotReturn,
+	/// The hypervisor does not support some required features (see
+	/// [`HvFeatures::require()`](feature_support::HvFeatures::require)).
+	/// Contains the bitmap of missing features.
+	MissingFeatures(u64),
+	/// The message is not available in the negotiated GHCB protocol
+	/// version (see
+	/// [`NegotiatedProtocol`](sev_info::NegotiatedProtocol)).
+	/// Contains the minimum version required by the message.
+	UnsupportedVersion(u16),
+	/// The GFN is outside the range covered by a
+	/// [`PageTracker`](page_tracker::PageTracker).
+	UntrackedGfn(u64),
+	/// The page is already in the requested state.
+	RedundantConversion(u64),
+	/// The hypervisor reported an error in a
+	/// [`PageStateResp`](page_state::PageStateResp) or
+	/// [`RunVmplResp`](run_vmpl::RunVmplResp). Contains the raw
+	/// error code. The MSR protocol does not define any error codes,
+	/// so their meaning is hypervisor-specific and they are not
+	/// decoded further.
+	Hypervisor(u32),
+	/// A request field does not fit in its encoding. Contains the
+	/// offending value.
+	OutOfRange(u64),
+	/// The address is not page-aligned, exceeds the physical address
+	/// width or has the C-bit set (see
+	/// [`AddrSpace`](addr::AddrSpace)). Contains the GPA.
+	InvalidGpa(u64),
+	/// The address of the page exceeds the physical address width or
+	/// has the C-bit set (see [`AddrSpace`](addr::AddrSpace)).
+	/// Contains the GFN.
+	InvalidGfn(u64),
 }
 
 /// Request/response codes for the MSR protocol. These are returned by
@@ -147,6 +254,33 @@ pub enum GhcbMsrInfo {
 	TERM_REQ = 0x100,
 }
 
+impl GhcbMsrInfo {
+	/// The minimum GHCB protocol version in which the message is
+	/// defined.
+	pub const fn min_version(&self) -> u16 {
+		match self {
+			Self::GHCB_GPA
+			| Self::SEV_INFO_RESP
+			| Self::SEV_INFO_REQ
+			| Self::CPUID_REQ
+			| Self::CPUID_RESP
+			| Self::TERM_REQ => 1,
+			Self::AP_RESET_HOLD_REQ
+			| Self::AP_RESET_HOLD_RESP
+			| Self::PREF_GHCB_GPA_REQ
+			| Self::PREF_GHCB_GPA_RESP
+			| Self::REG_GHCB_GPA_REQ
+			| Self::REG_GHCB_GPA_RESP
+			| Self::STATE_CHANGE_REQ
+			| Self::STATE_CHANGE_RESP
+			| Self::RUN_VMPL_REQ
+			| Self::RUN_VMPL_RESP
+			| Self::FEAT_SUPPORT_REQ
+			| Self::FEAT_SUPPORT_RESP => 2,
+		}
+	}
+}
+
 impl TryFrom<u16> for GhcbMsrInfo {
 	type Error = GhcbMsrError;
 	fn try_from(val: u16) -> Result<Self, Self::Error> {
@@ -202,8 +336,10 @@ impl TryFrom<u16> for GhcbMsrInfo {
 	}
 }
 
-/// Trait implemented by all GHCB MSR requests.
-pub trait GhcbMsrRequest {
+/// Trait implemented by all GHCB MSR requests. Requests can also be
+/// decoded from a raw MSR value, which is useful on the hypervisor
+/// side of the protocol.
+pub trait GhcbMsrRequest: TryFrom<u64, Error = GhcbMsrError> {
 	type Resp: GhcbMsrResp;
 	/// The GHCBInfo segment of the MSR.
 	fn info(&self) -> GhcbMsrInfo;
@@ -220,21 +356,49 @@ pub trait GhcbMsrRequest {
 	/// The numeric value of the request that should be written to
 	/// the MSR.
 	fn msr(&self) -> u64 {
-		((self.data() & 0xfffffffffffff) << 12)
-			| (self.info() as u64 & 0xfff)
+		build_msr(self.info(), self.data())
 	}
 }
 
 /// Trait implemented by all GHCB MSR responses.
 pub trait GhcbMsrResp: TryFrom<u64, Error = GhcbMsrError> {}
 
+/// Trait implemented by the GHCB MSR responses that the hypervisor
+/// sends, to encode them into a raw MSR value. This is useful on the
+/// hypervisor side of the protocol.
+pub trait GhcbMsrRespEncode: GhcbMsrResp {
+	/// The GHCBInfo segment of the MSR.
+	fn info(&self) -> GhcbMsrInfo;
+	/// The GHCBData segment of the MSR.
+	fn data(&self) -> u64;
+	/// The numeric value of the response that should be written to
+	/// the MSR.
+	fn msr(&self) -> u64 {
+		build_msr(self.info(), self.data())
+	}
+}
+
 fn parse_msr(msr: u64) -> (u16, u64) {
 	((msr & 0xfff) as u16, msr >> 12)
 }
 
+fn build_msr(info: GhcbMsrInfo, data: u64) -> u64 {
+	((data & 0xfffffffffffff) << 12) | (info as u64 & 0xfff)
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use addr::{Gfn, Gpa};
+
+	/// Register a GHCB page at GFN 0x42 with the mock hypervisor.
+	fn registered(
+		mut hv: mock::MockHypervisor<'_>,
+	) -> mock::MockHypervisor<'_> {
+		let req = register_ghcb::RegisterGhcbReq::new(Gfn::new(0x42));
+		transport::exchange(&mut hv, &req).unwrap();
+		hv
+	}
 
 	#[should_panic]
 	#[test]
@@ -242,4 +406,791 @@ mod tests {
 		let req = sev_info::SevInfoReq::new();
 		let _ = req.response(0).unwrap();
 	}
+
+	struct FixedResp {
+		msr: u64,
+		resp: u64,
+	}
+
+	impl transport::GhcbMsrTransport for FixedResp {
+		fn write_msr(&mut self, val: u64) {
+			self.msr = val;
+		}
+		fn read_msr(&mut self) -> u64 {
+			self.msr
+		}
+		fn vmgexit(&mut self) {
+			self.msr = self.resp;
+		}
+	}
+
+	#[test]
+	fn exchange_restores_msr() {
+		let resp = sev_info::SevInfoResp::new(1, 2, 51).msr();
+		let mut t = FixedResp {
+			msr: 0x1234000,
+			resp,
+		};
+		let req = sev_info::SevInfoReq::new();
+		let r = transport::exchange(&mut t, &req).unwrap();
+		assert_eq!((r.min_ver, r.max_ver, r.enc_bit_no), (1, 2, 51));
+		assert_eq!(t.msr, 0x1234000);
+	}
+
+	#[test]
+	fn mock_register_per_vcpu() {
+		let mut hv = mock::MockHypervisor::new();
+		for vcpu in 0..2 {
+			hv.set_vcpu(vcpu);
+			let gfn = 0x100 + vcpu as u64;
+			let req =
+				register_ghcb::RegisterGhcbReq::new(Gfn::new(gfn));
+			let resp = transport::exchange(&mut hv, &req).unwrap();
+			assert_eq!(resp.gfn, Gfn::new(gfn));
+		}
+		assert_eq!(hv.registered_gfn(0), Some(Gfn::new(0x100)));
+		assert_eq!(hv.registered_gfn(1), Some(Gfn::new(0x101)));
+		assert_eq!(hv.registered_gfn(2), None);
+	}
+
+	#[test]
+	fn mock_misbehavior() {
+		use mock::Misbehavior;
+		use transport::exchange;
+
+		let mut hv = mock::MockHypervisor::new();
+		let info = sev_info::SevInfoReq::new();
+		let cpuid = cpuid::CpuidReq::new(0, cpuid::CpuidReg::EAX);
+		let reg = register_ghcb::RegisterGhcbReq::new(Gfn::new(0x10));
+		let term = termination::TerminationReq::from_reason(
+			termination::TerminationReason::GeneralTermination,
+		);
+
+		hv.misbehave(Misbehavior::InvalidInfo);
+		let err = exchange(&mut hv, &info).err();
+		assert_eq!(err, Some(GhcbMsrError::InvalidInfo));
+		hv.misbehave(Misbehavior::MismatchedInfo);
+		let err = exchange(&mut hv, &info).err();
+		assert_eq!(err, Some(GhcbMsrError::MismatchedInfo));
+		hv.misbehave(Misbehavior::ReservedBits);
+		let err = exchange(&mut hv, &cpuid).err();
+		assert_eq!(err, Some(GhcbMsrError::InvalidData));
+		hv.misbehave(Misbehavior::ReservedBits);
+		let vmpl = run_vmpl::RunVmplReq::new(0);
+		let err = exchange(&mut hv, &vmpl).err();
+		assert_eq!(err, Some(GhcbMsrError::InvalidData));
+		hv.misbehave(Misbehavior::ReservedBits);
+		assert!(exchange(&mut hv, &reg).is_ok());
+		hv.misbehave(Misbehavior::MismatchedGfn);
+		let err = exchange(&mut hv, &reg).err();
+		assert_eq!(err, Some(GhcbMsrError::MismatchedData(0x11)));
+		hv.misbehave(Misbehavior::MismatchedReg);
+		let err = exchange(&mut hv, &cpuid).err();
+		let ebx = cpuid::CpuidReg::EBX as u64;
+		assert_eq!(err, Some(GhcbMsrError::MismatchedData(ebx)));
+		let err = exchange(&mut hv, &term).err();
+		assert_eq!(err, Some(GhcbMsrError::ShouldNotReturn));
+		assert_eq!(hv.termination(), Some((0, 0)));
+	}
+
+	#[test]
+	fn decode_requests() {
+		use page_state::{PageOp, PageStateReq};
+		use termination::TerminationReq;
+
+		let req = PageStateReq::new(Gfn::new(0x1234), PageOp::Shared);
+		let dec = PageStateReq::try_from(req.msr()).unwrap();
+		assert_eq!(dec, req);
+		assert_eq!(
+			(dec.gfn(), dec.op()),
+			(Gfn::new(0x1234), PageOp::Shared)
+		);
+
+		let bad_op = (3 << 52) | (0x1234 << 12) | 0x014;
+		let err = PageStateReq::try_from(bad_op).err();
+		assert_eq!(err, Some(GhcbMsrError::InvalidData));
+		let reserved = (1 << 56) | req.msr();
+		let err = PageStateReq::try_from(reserved).err();
+		assert_eq!(err, Some(GhcbMsrError::InvalidData));
+		let err = run_vmpl::RunVmplReq::try_from(req.msr()).err();
+		assert_eq!(err, Some(GhcbMsrError::MismatchedInfo));
+
+		let term = TerminationReq::try_from(0x0080_3100).unwrap();
+		assert_eq!((term.code_set(), term.reason()), (3, 0x80));
+		assert_eq!(term.ghcb_reason(), None);
+		let term = TerminationReq::try_from(0x100).unwrap();
+		assert_eq!((term.code_set(), term.reason()), (0, 0));
+		assert_eq!(
+			term.ghcb_reason(),
+			Some(termination::TerminationReason::GeneralTermination)
+		);
+	}
+
+	#[test]
+	fn encode_responses() {
+		use page_state::PageStateResp;
+		use sev_info::SevInfoResp;
+
+		let resp = SevInfoResp::new(1, 2, 51);
+		assert_eq!(resp.msr(), 0x0002_0001_3300_0001);
+		assert_eq!(SevInfoResp::try_from(resp.msr()), Ok(resp));
+
+		let resp = PageStateResp::with_error(7);
+		assert_eq!(PageStateResp::try_from(resp.msr()), Ok(resp));
+		let resp =
+			register_ghcb::RegisterGhcbResp::for_gfn(Gfn::new(0xabc));
+		let req =
+			register_ghcb::RegisterGhcbReq::new(Gfn::new(0xabc));
+		assert_eq!(req.response(resp.msr()), Ok(resp));
+	}
+
+	#[test]
+	fn cpuid_leaves() {
+		use cpuid::CpuidLeaf;
+		use mock::MockCpuidEntry;
+
+		let table = [
+			MockCpuidEntry {
+				function: 0,
+				index: 0,
+				regs: [1, 0x68747541, 0x444d4163, 0x69746e65],
+			},
+			MockCpuidEntry {
+				function: 1,
+				index: 0,
+				regs: [0xa00f11, 0x800, 0x7ef8320b, 0x178bfbff],
+			},
+		];
+		let mut hv = mock::MockHypervisor::new().with_cpuid(&table);
+		let max = CpuidLeaf::fetch(&mut hv, 0).unwrap().eax;
+		let leaves = CpuidLeaf::fetch_range(&mut hv, 0..=max);
+		assert_eq!(leaves.count(), table.len());
+		let leaves = CpuidLeaf::fetch_range(&mut hv, 0..=max);
+		for (entry, leaf) in table.iter().zip(leaves) {
+			let (function, leaf) = leaf.unwrap();
+			assert_eq!(function, entry.function);
+			assert_eq!(
+				[leaf.eax, leaf.ebx, leaf.ecx, leaf.edx],
+				entry.regs
+			);
+		}
+		let last = u32::MAX..=u32::MAX;
+		assert_eq!(CpuidLeaf::fetch_range(&mut hv, last).count(), 1);
+	}
+
+	#[test]
+	fn hv_features() {
+		extern crate std;
+		use feature_support::{FeatureSupportReq, HvFeatures};
+		use std::format;
+
+		let feats = HvFeatures::SEV_SNP
+			| HvFeatures::SNP_MULTI_VMPL
+			| HvFeatures::from_bits(1 << 40);
+		let mut hv = mock::MockHypervisor::new().with_features(feats);
+		let req = FeatureSupportReq::new();
+		let resp = transport::exchange(&mut hv, &req).unwrap();
+		assert_eq!(resp.features, feats);
+		assert_eq!(resp.features.unknown(), 1 << 40);
+		assert_eq!(
+			format!("{}", resp.features),
+			"SEV-SNP, multi-VMPL, 0x10000000000"
+		);
+		assert_eq!(
+			format!("{:?}", HvFeatures::empty()),
+			"HvFeatures(empty)"
+		);
+		assert!(resp.features.require(HvFeatures::SEV_SNP).is_ok());
+		assert_eq!(
+			resp.features.require(HvFeatures::SNP_AP_CREATION),
+			Err(GhcbMsrError::MissingFeatures(1 << 1))
+		);
+	}
+
+	#[test]
+	fn negotiate_protocol() {
+		use sev_info::SevInfoResp;
+		use termination::TerminationReason;
+
+		let resp = SevInfoResp::new(1, 2, 51);
+		let proto = resp.negotiate(1, 3).unwrap();
+		assert_eq!((proto.version(), proto.enc_bit_no()), (2, 51));
+		assert_eq!(resp.negotiate(1, 1).unwrap().version(), 1);
+
+		let term = SevInfoResp::new(2, 2, 51).negotiate(1, 1).err();
+		let term = term.unwrap();
+		assert_eq!(
+			term.ghcb_reason(),
+			Some(TerminationReason::GhcbProtRangeNotSupported)
+		);
+		assert!(resp.negotiate(3, 4).is_err());
+	}
+
+	#[test]
+	fn version_gating() {
+		use page_state::{PageOp, PageStateReq};
+
+		let v1 = sev_info::SevInfoResp::new(1, 2, 51)
+			.negotiate(1, 1)
+			.unwrap();
+		let req = PageStateReq::new(Gfn::new(0x10), PageOp::Shared);
+		let err = v1.check(req).err();
+		assert_eq!(err, Some(GhcbMsrError::UnsupportedVersion(2)));
+		assert!(v1.check(sev_info::SevInfoReq::new()).is_ok());
+	}
+
+	#[test]
+	fn session_flow() {
+		use session::GhcbSession;
+
+		let mut hv = mock::MockHypervisor::new();
+		let session =
+			GhcbSession::new(&mut hv).negotiate(1, 2).unwrap();
+		assert_eq!(session.protocol().version(), 2);
+		let session = session.register(Gfn::new(0x80)).unwrap();
+		assert_eq!(session.gfn(), Gfn::new(0x80));
+		let hv = session.into_transport();
+		assert_eq!(hv.registered_gfn(0), Some(Gfn::new(0x80)));
+		assert_eq!(
+			transport::GhcbMsrTransport::read_msr(hv),
+			0x80 << 12
+		);
+
+		let mut hv = mock::MockHypervisor::new().with_versions(2, 2);
+		let err = GhcbSession::new(&mut hv).negotiate(1, 1).err();
+		assert_eq!(err, Some(GhcbMsrError::ShouldNotReturn));
+		assert_eq!(hv.termination(), Some((0, 1)));
+	}
+
+	#[test]
+	fn ghcb_layout() {
+		use ghcb::{Ghcb, GhcbField};
+
+		assert_eq!(core::mem::size_of::<Ghcb>(), ghcb::GHCB_SIZE);
+		let mut ghcb = Ghcb::new();
+		ghcb.set_sw_exit_code(0x72);
+		ghcb.set_rax(0x0102030405060708);
+		ghcb.set_cpl(3);
+		ghcb.set_protocol_version(2);
+		ghcb.shared_buffer_mut()[0] = 0xaa;
+		let page = ghcb.as_bytes();
+		assert_eq!(page[0x390], 0x72);
+		assert_eq!(page[0x1f8..0x200], [8, 7, 6, 5, 4, 3, 2, 1]);
+		assert_eq!((page[0xcb], page[0xcc]), (3, 0));
+		assert_eq!(page[0xffa], 2);
+		assert_eq!(page[0x800], 0xaa);
+		assert_eq!(ghcb.get(GhcbField::Rax), 0x0102030405060708);
+	}
+
+	#[test]
+	fn ghcb_valid_bitmap() {
+		use ghcb::{Ghcb, GhcbError, GhcbField};
+
+		let mut ghcb = Ghcb::new();
+		ghcb.set_protocol_version(2);
+		ghcb.set_rcx(0x10);
+		ghcb.set_cpl(0);
+		assert_eq!(ghcb.valid_bitmap()[0x308 / 64], 1 << 1);
+		assert_eq!(ghcb.valid_bitmap()[3], 1 << 1);
+		assert_eq!(ghcb.get_valid(GhcbField::Rcx), Ok(0x10));
+		assert_eq!(
+			ghcb.get_valid(GhcbField::Rax),
+			Err(GhcbError::FieldNotValid(GhcbField::Rax))
+		);
+		ghcb.clear();
+		assert!(!ghcb.is_valid(GhcbField::Rcx));
+		assert_eq!(ghcb.rcx(), 0);
+		assert_eq!(ghcb.protocol_version(), 2);
+
+		// X87StateGpa lies past the bitmap: setting it must not
+		// touch the bitmap, and the bitmap must not alias it.
+		assert_eq!(GhcbField::X87StateGpa.valid_bit(), None);
+		ghcb.set(GhcbField::X87StateGpa, 0x1000);
+		assert_eq!(ghcb.get(GhcbField::X87StateGpa), 0x1000);
+		assert_eq!(ghcb.valid_bitmap(), [0; 16]);
+		ghcb.set_rax(1);
+		assert_eq!(ghcb.get(GhcbField::X87StateGpa), 0x1000);
+		assert!(!ghcb.is_valid(GhcbField::X87StateGpa));
+	}
+
+	#[test]
+	fn nae_exit_codes() {
+		extern crate std;
+		use nae::SwExitCode;
+		use std::format;
+
+		let code = SwExitCode::try_from(0x8000_0010).unwrap();
+		assert_eq!(code, SwExitCode::SNP_PSC);
+		assert_eq!(
+			format!("{}", code),
+			"SNP page state change (0x80000010)"
+		);
+		assert!(SwExitCode::CPUID
+			.outputs()
+			.contains(&ghcb::GhcbField::Rbx));
+		assert_eq!(
+			SwExitCode::try_from(0x1234),
+			Err(ghcb::GhcbError::InvalidExitCode(0x1234))
+		);
+	}
+
+	#[test]
+	fn cpuid_ghcb() {
+		use cpuid::{CpuidGhcbReq, CpuidLeaf};
+		use mock::MockCpuidEntry;
+		use transport::exchange_ghcb;
+
+		let entry = |index, eax| MockCpuidEntry {
+			function: 0xd,
+			index,
+			regs: [eax, 0, 0, 0],
+		};
+		let table = [entry(0, 0x7), entry(1, 0xf)];
+		let mut hv = registered(
+			mock::MockHypervisor::new().with_cpuid(&table),
+		);
+
+		let req =
+			CpuidGhcbReq::new(0xd, 1).with_xcr0(0x7).with_xss(0);
+		let leaf = exchange_ghcb(&mut hv, &req).unwrap();
+		assert_eq!(
+			leaf,
+			CpuidLeaf {
+				eax: 0xf,
+				..Default::default()
+			}
+		);
+		assert_eq!(
+			transport::GhcbTransport::ghcb(&mut hv).xcr0(),
+			0x7
+		);
+	}
+
+	#[test]
+	fn ghcb_serial() {
+		use core::fmt::Write;
+		use ioio::{GhcbSerial, IoDirection, IoSize, IoioInfo, COM1};
+
+		let info =
+			IoioInfo::new(COM1, IoDirection::Out, IoSize::Byte);
+		assert_eq!(info.encode(), 0x03f8_0210);
+		assert_eq!(IoioInfo::try_from(info.encode()), Ok(info));
+
+		let mut hv = registered(mock::MockHypervisor::new());
+		hv.set_port(COM1 + 5, 0x20);
+		let mut serial = GhcbSerial::new(&mut hv, COM1);
+		write!(serial, "o\nk").unwrap();
+		let writes =
+			[b'o', b'\r', b'\n', b'k'].map(|b| (COM1, b as u32));
+		assert_eq!(hv.io_writes(), writes);
+
+		// A transmitter that never empties does not hang the guest.
+		hv.set_port(COM1 + 5, 0);
+		GhcbSerial::new(&mut hv, COM1).write_byte(b'!').unwrap();
+		assert_eq!(hv.io_writes().len(), 5);
+	}
+
+	#[test]
+	fn mmio_access() {
+		use ghcb::GhcbError;
+		use mmio::*;
+
+		let mut hv = registered(
+			mock::MockHypervisor::new()
+				.with_mmio_base(Gpa::new(0xfee00000)),
+		);
+
+		mmio_write_u32(&mut hv, Gpa::new(0xfee00010), 0xdeadbeef)
+			.unwrap();
+		assert_eq!(hv.mmio()[0x10..0x14], [0xef, 0xbe, 0xad, 0xde]);
+		assert_eq!(
+			mmio_read_u16(&mut hv, Gpa::new(0xfee00012)),
+			Ok(0xdead)
+		);
+		let mut buf = [0u8; 20];
+		mmio_read(&mut hv, Gpa::new(0xfee00000), &mut buf).unwrap();
+		assert_eq!(buf[0x10], 0xef);
+
+		hv.misbehave(mock::Misbehavior::MovedScratch);
+		let err = mmio_read_u8(&mut hv, Gpa::new(0xfee00000)).err();
+		assert!(matches!(err, Some(GhcbError::ScratchChanged(_))));
+		let err =
+			MmioReadReq::new(Gpa::new(0xfee00000), MAX_MMIO_LEN + 1)
+				.err();
+		assert_eq!(
+			err,
+			Some(GhcbError::InvalidLength(MAX_MMIO_LEN + 1))
+		);
+	}
+
+	#[test]
+	fn msr_access() {
+		use exit_info::{EventInjection, EventType};
+		use ghcb::GhcbError;
+
+		let mut hv = registered(mock::MockHypervisor::new());
+
+		hv.set_msr(0xc000_0103, 7);
+		assert_eq!(msr::read_msr(&mut hv, 0xc000_0103), Ok(7));
+		msr::write_msr(&mut hv, 0x830, 0x1_0000_00ff).unwrap();
+		assert_eq!(hv.msr_value(0x830), Some(0x1_0000_00ff));
+		assert_eq!(msr::read_msr(&mut hv, 0x830), Ok(0x1_0000_00ff));
+		assert_eq!(
+			msr::read_msr(&mut hv, 0x1234),
+			Err(GhcbError::EventInjection(
+				EventInjection::new(13, EventType::Exception)
+					.with_error_code(0)
+			))
+		);
+	}
+
+	#[test]
+	fn exit_info_errors() {
+		use exit_info::*;
+		use ghcb::GhcbError;
+
+		let nmi = EventInjection::new(2, EventType::Nmi);
+		assert_eq!(EventInjection::try_from(nmi.encode()), Ok(nmi));
+		assert_eq!(
+			check_exit_info(1, nmi.encode()),
+			Err(GhcbError::EventInjection(nmi))
+		);
+		assert_eq!(
+			check_exit_info(1, 0x30d),
+			Err(GhcbError::InvalidExitInfo)
+		);
+		assert_eq!(
+			check_exit_info(2, 3),
+			Err(GhcbError::Hypervisor(
+				GhcbErrorReason::InvalidScratchArea
+			))
+		);
+		assert_eq!(
+			check_exit_info(2, 0x40),
+			Err(GhcbError::Hypervisor(GhcbErrorReason::Unknown(
+				0x40
+			)))
+		);
+		assert_eq!(check_exit_info(0xabcd_0000_0000, 0), Ok(()));
+
+		let mut hv = registered(mock::MockHypervisor::new());
+		assert_eq!(
+			mmio::mmio_read_u8(&mut hv, Gpa::new(0x1000)),
+			Err(GhcbError::Hypervisor(GhcbErrorReason::InvalidInput))
+		);
+	}
+
+	#[test]
+	fn psc_buffer() {
+		use ghcb::GhcbError;
+		use page_state::PageOp;
+		use psc::*;
+
+		let mut psc = PscBuffer::new();
+		let op = PageOp::Shared;
+		assert_eq!(psc.push_range(Gfn::new(0x100), 300, op), Ok(253));
+		assert!(psc.is_full());
+		let entry = psc.entries()[1];
+		assert_eq!(entry.encode(), 0x0020_0000_0010_1000);
+		assert_eq!(PscEntry::try_from(entry.encode()), Ok(entry));
+		let big = Gfn::new(1 << 40);
+		let err = PscEntry::try_new(big, op.into(), PageSize::Size4K);
+		assert_eq!(err, Err(GhcbError::OutOfRange(1 << 40)));
+
+		let mut hv = registered(mock::MockHypervisor::new());
+		let err = change_range(&mut hv, big, 1, PageOp::Shared);
+		assert_eq!(err, Err(GhcbError::OutOfRange(1 << 40)));
+		let last = Gfn::new((1 << 40) - 1);
+		let err = change_range(&mut hv, last, 2, PageOp::Shared);
+		assert_eq!(err, Err(GhcbError::OutOfRange(1 << 40)));
+		assert_eq!(hv.page_states().len(), 0);
+		change_range(&mut hv, Gfn::new(0x100), 20, PageOp::Shared)
+			.unwrap();
+		assert_eq!(hv.page_states().len(), 20);
+		assert_eq!(
+			hv.page_states()[19],
+			(Gfn::new(0x113), PageOp::Shared)
+		);
+
+		psc.clear();
+		psc.push_range(Gfn::new(0x200), 10, PageOp::Private)
+			.unwrap();
+		let mut fresh = PscBuffer::new();
+		fresh
+			.push_range(Gfn::new(0x200), 10, PageOp::Private)
+			.unwrap();
+		assert_eq!(psc, fresh);
+		hv.misbehave(mock::Misbehavior::FailPageState);
+		let err = submit(&mut hv, &mut psc);
+		assert_eq!(err, Err(GhcbError::PageStateChange(0, 0x16)));
+		submit(&mut hv, &mut psc).unwrap();
+		assert!(psc.is_done());
+		assert_eq!(psc.entries()[9].cur_page(), 1);
+
+		// A failure after partial progress is resumed from the
+		// failing entry.
+		let mut failing = registered(
+			mock::MockHypervisor::new()
+				.with_failing_gfn(Gfn::new(0x305)),
+		);
+		psc.clear();
+		psc.push_range(Gfn::new(0x300), 10, PageOp::Private)
+			.unwrap();
+		let err = submit(&mut failing, &mut psc);
+		assert_eq!(err, Err(GhcbError::PageStateChange(5, 0x16)));
+		assert_eq!(psc.cur_entry(), 5);
+		assert_eq!(failing.page_states().len(), 5);
+		submit(&mut hv, &mut psc).unwrap();
+		assert_eq!(hv.page_states().len(), 35);
+		assert_eq!(
+			hv.page_states()[30],
+			(Gfn::new(0x305), PageOp::Private)
+		);
+	}
+
+	#[test]
+	fn psc_large_pages() {
+		use ghcb::GhcbError;
+		use page_state::PageOp;
+		use psc::*;
+
+		let mut plan = RangePlanner::new(Gfn::new(0x1fe), 0x404);
+		assert_eq!(plan.clone().count(), 6);
+		assert_eq!(
+			plan.nth(2),
+			Some((Gfn::new(0x200), PageSize::Size2M))
+		);
+		assert_eq!(
+			plan.next(),
+			Some((Gfn::new(0x400), PageSize::Size2M))
+		);
+		assert_eq!(
+			plan.nth(1),
+			Some((Gfn::new(0x601), PageSize::Size4K))
+		);
+		assert_eq!(plan.next(), None);
+
+		let mut psc = PscBuffer::new();
+		assert_eq!(
+			psc.push_range(Gfn::new(0x1fe), 0x404, PageOp::Private),
+			Ok(0x404)
+		);
+		let entry = psc.entries()[2];
+		assert_eq!(PscEntry::try_from(entry.encode()), Ok(entry));
+
+		let mut hv = registered(mock::MockHypervisor::new());
+		submit(&mut hv, &mut psc).unwrap();
+		assert_eq!(psc.entries()[2].cur_page(), 512);
+		assert_eq!(hv.page_states().len(), 6);
+
+		psc.clear();
+		let entry = PscEntry::new(
+			Gfn::new(0x201),
+			PscOp::Psmash,
+			PageSize::Size4K,
+		);
+		psc.push(entry).unwrap();
+		assert_eq!(
+			submit(&mut hv, &mut psc),
+			Err(GhcbError::PageStateChange(0, 0x1_0000_0002))
+		);
+	}
+
+	#[test]
+	fn page_range_rollback() {
+		use page_state::*;
+
+		let mut hv = mock::MockHypervisor::new()
+			.with_failing_gfn(Gfn::new(0x12));
+		let mut validated = [true; 4];
+		let report = convert_range(
+			&mut hv,
+			Gfn::new(0x10),
+			4,
+			PageOp::Shared,
+			|gfn, op| -> Result<(), ()> {
+				validated[(gfn.as_u64() - 0x10) as usize] =
+					op == PageOp::Private;
+				Ok(())
+			},
+		)
+		.unwrap_err();
+		assert_eq!(report.failed_gfn, Gfn::new(0x12));
+		assert_eq!(
+			report.error,
+			ConversionError::Msr(GhcbMsrError::Hypervisor(1))
+		);
+		assert_eq!((report.converted, report.rolled_back), (2, 2));
+		assert_eq!(report.undo_error, None);
+		assert_eq!(report.rollback_error, None);
+		assert_eq!(validated, [true; 4]);
+		assert_eq!(
+			hv.page_states(),
+			[
+				(Gfn::new(0x10), PageOp::Shared),
+				(Gfn::new(0x11), PageOp::Shared),
+				(Gfn::new(0x11), PageOp::Private),
+				(Gfn::new(0x10), PageOp::Private),
+			]
+		);
+
+		let res = convert_range(
+			&mut hv,
+			Gfn::new(0x20),
+			2,
+			PageOp::Private,
+			|_, _| Err::<(), _>("pvalidate"),
+		);
+		let report = res.unwrap_err();
+		assert_eq!(
+			report.error,
+			ConversionError::Callback("pvalidate")
+		);
+		assert_eq!(report.converted, 0);
+		assert_eq!(
+			hv.page_states()[5],
+			(Gfn::new(0x20), PageOp::Shared)
+		);
+
+		// The refused page cannot be validated again.
+		let res = convert_range(
+			&mut hv,
+			Gfn::new(0x12),
+			1,
+			PageOp::Shared,
+			|_, op| match op {
+				PageOp::Shared => Ok(()),
+				PageOp::Private => Err("revalidate"),
+			},
+		);
+		let report = res.unwrap_err();
+		assert_eq!(
+			report.error,
+			ConversionError::Msr(GhcbMsrError::Hypervisor(1))
+		);
+		assert_eq!(
+			report.undo_error,
+			Some(ConversionError::Callback("revalidate"))
+		);
+	}
+
+	#[test]
+	fn page_tracker() {
+		use page_state::PageOp;
+		use page_tracker::PageTracker;
+
+		let mut bitmap = [u64::MAX; 2];
+		let gfns = |start, end| Gfn::new(start)..Gfn::new(end);
+		let base = Gfn::new(0x1000);
+		let mut tracker = PageTracker::new(base, &mut bitmap);
+		assert_eq!(tracker.gfns(), gfns(0x1000, 0x1080));
+		assert_eq!(
+			tracker.request(Gfn::new(0x1005), PageOp::Private),
+			Err(GhcbMsrError::RedundantConversion(0x1005))
+		);
+		assert_eq!(
+			tracker.request(Gfn::new(0x1080), PageOp::Shared),
+			Err(GhcbMsrError::UntrackedGfn(0x1080))
+		);
+
+		let mut hv = mock::MockHypervisor::new();
+		for gfn in 0x1005..0x1007 {
+			let req = tracker
+				.request(Gfn::new(gfn), PageOp::Shared)
+				.unwrap();
+			transport::exchange(&mut hv, &req).unwrap();
+			tracker.record(Gfn::new(gfn), PageOp::Shared).unwrap();
+		}
+		let mut regions = tracker.regions();
+		assert_eq!(
+			regions.next(),
+			Some((gfns(0x1000, 0x1005), PageOp::Private))
+		);
+		assert_eq!(
+			regions.next(),
+			Some((gfns(0x1005, 0x1007), PageOp::Shared))
+		);
+		assert_eq!(
+			regions.next(),
+			Some((gfns(0x1007, 0x1080), PageOp::Private))
+		);
+		assert_eq!(regions.next(), None);
+	}
+
+	#[test]
+	fn hv_error_codes() {
+		use run_vmpl::{RunVmplReq, RunVmplResp};
+
+		let req = RunVmplReq::new(1);
+		let resp = RunVmplResp::with_error(0x80);
+		assert_eq!(
+			req.response(resp.msr()),
+			Err(GhcbMsrError::Hypervisor(0x80))
+		);
+		let resp = RunVmplResp::new();
+		assert_eq!(req.response(resp.msr()), Ok(resp));
+	}
+
+	#[test]
+	fn checked_constructors() {
+		use page_state::{PageOp, PageStateReq};
+		use termination::TerminationReq;
+
+		let err = GhcbMsrError::OutOfRange(1 << 40);
+		let res =
+			PageStateReq::try_new(Gfn::new(1 << 40), PageOp::Shared);
+		assert_eq!(res, Err(err));
+		let req = PageStateReq::try_new(
+			Gfn::new(0xff_ffff_ffff),
+			PageOp::Shared,
+		);
+		assert_eq!(req.unwrap().msr(), 0x002f_ffff_ffff_f014);
+		let gfn = 1 << 52;
+		let res =
+			register_ghcb::RegisterGhcbReq::try_new(Gfn::new(gfn));
+		assert_eq!(res, Err(GhcbMsrError::OutOfRange(gfn)));
+		let res = run_vmpl::RunVmplReq::try_new(4);
+		assert_eq!(res, Err(GhcbMsrError::OutOfRange(4)));
+		let res = TerminationReq::try_new(0x10, 1);
+		assert_eq!(res, Err(GhcbMsrError::OutOfRange(0x10)));
+		assert!(TerminationReq::try_new(0xf, 1).is_ok());
+	}
+
+	#[test]
+	fn addr_space() {
+		use addr::AddrSpace;
+
+		let resp = sev_info::SevInfoResp::new(1, 2, 51);
+		let space = AddrSpace::from_sev_info(&resp, 52);
+		assert_eq!(space.enc_mask(), 1 << 51);
+		let gpa = Gpa::new(0x1234_5678);
+		assert_eq!(gpa.gfn(), Gfn::new(0x12345));
+		assert_eq!(gpa.align_down(), Gpa::new(0x1234_5000));
+		assert_eq!(
+			Gfn::try_from(gpa),
+			Err(GhcbMsrError::InvalidGpa(0x1234_5678))
+		);
+		assert_eq!(space.check_gpa(gpa), Ok(gpa));
+		let err = GhcbMsrError::InvalidGfn(1 << 39);
+		assert_eq!(space.check_gfn(Gfn::new(1 << 39)), Err(err));
+		let err = GhcbMsrError::InvalidGpa(1 << 52);
+		assert_eq!(space.check_gpa(Gpa::new(1 << 52)), Err(err));
+		assert_eq!(Gfn::new(1 << 52).gpa(), None);
+		assert_eq!(
+			Gpa::try_from(Gfn::new(1 << 52)),
+			Err(GhcbMsrError::InvalidGfn(1 << 52))
+		);
+		assert_eq!(
+			Gpa::try_from(Gfn::new(0x12345)),
+			Ok(Gpa::new(0x1234_5000))
+		);
+
+		let mut hv = mock::MockHypervisor::new();
+		let session = session::GhcbSession::new(&mut hv);
+		let session = session.negotiate(1, 2).unwrap();
+		let res = session.register(Gfn::new(1 << 39));
+		let err = GhcbMsrError::InvalidGfn(1 << 39);
+		assert_eq!(res.err(), Some(err));
+		assert_eq!(hv.registered_gfn(0), None);
+	}
 }
@@ -0,0 +1,207 @@
+use crate::addr::Gpa;
+use crate::ghcb::{
+	Ghcb, GhcbError, GhcbRequest, SHARED_BUFFER_OFFSET,
+	SHARED_BUFFER_SIZE,
+};
+use crate::nae::SwExitCode;
+use crate::transport::{exchange_ghcb, GhcbTransport};
+
+/// Maximum length of a single MMIO access, limited by the size of
+/// the shared buffer.
+pub const MAX_MMIO_LEN: usize = SHARED_BUFFER_SIZE;
+
+const fn check_len(len: usize) -> Result<(), GhcbError> {
+	if len == 0 || len > MAX_MMIO_LEN {
+		return Err(GhcbError::InvalidLength(len));
+	}
+	Ok(())
+}
+
+/// A request to read `len` bytes of emulated MMIO at `gpa`. The
+/// hypervisor places the data at the start of the shared buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmioReadReq {
+	gpa: Gpa,
+	len: usize,
+}
+
+impl MmioReadReq {
+	/// Fails if `len` is zero or larger than [`MAX_MMIO_LEN`].
+	pub const fn new(
+		gpa: Gpa,
+		len: usize,
+	) -> Result<Self, GhcbError> {
+		if let Err(e) = check_len(len) {
+			return Err(e);
+		}
+		Ok(Self { gpa, len })
+	}
+}
+
+impl GhcbRequest for MmioReadReq {
+	type Resp = ();
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::MMIO_READ
+	}
+	fn exit_info_1(&self) -> u64 {
+		self.gpa.as_u64()
+	}
+	fn exit_info_2(&self) -> u64 {
+		self.len as u64
+	}
+	fn prepare(&self, ghcb: &mut Ghcb, ghcb_gpa: Gpa) {
+		let scratch = ghcb_gpa + SHARED_BUFFER_OFFSET as u64;
+		ghcb.set_sw_scratch(scratch.as_u64());
+	}
+	fn response(&self, ghcb: &Ghcb) -> Result<Self::Resp, GhcbError> {
+		if ghcb.sw_exit_info_2() > self.len as u64 {
+			return Err(GhcbError::InvalidExitInfo);
+		}
+		Ok(())
+	}
+}
+
+/// A request to write data to emulated MMIO at `gpa`. The data is
+/// passed to the hypervisor through the shared buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmioWriteReq<'a> {
+	gpa: Gpa,
+	data: &'a [u8],
+}
+
+impl<'a> MmioWriteReq<'a> {
+	/// Fails if `data` is empty or larger than [`MAX_MMIO_LEN`].
+	pub const fn new(
+		gpa: Gpa,
+		data: &'a [u8],
+	) -> Result<Self, GhcbError> {
+		if let Err(e) = check_len(data.len()) {
+			return Err(e);
+		}
+		Ok(Self { gpa, data })
+	}
+}
+
+impl GhcbRequest for MmioWriteReq<'_> {
+	type Resp = ();
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::MMIO_WRITE
+	}
+	fn exit_info_1(&self) -> u64 {
+		self.gpa.as_u64()
+	}
+	fn exit_info_2(&self) -> u64 {
+		self.data.len() as u64
+	}
+	fn prepare(&self, ghcb: &mut Ghcb, ghcb_gpa: Gpa) {
+		ghcb.shared_buffer_mut()[..self.data.len()]
+			.copy_from_slice(self.data);
+		let scratch = ghcb_gpa + SHARED_BUFFER_OFFSET as u64;
+		ghcb.set_sw_scratch(scratch.as_u64());
+	}
+	fn response(
+		&self,
+		_ghcb: &Ghcb,
+	) -> Result<Self::Resp, GhcbError> {
+		Ok(())
+	}
+}
+
+/// Read `buf.len()` bytes of emulated MMIO at `gpa` into `buf`.
+pub fn mmio_read<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+	buf: &mut [u8],
+) -> Result<(), GhcbError> {
+	let req = MmioReadReq::new(gpa, buf.len())?;
+	exchange_ghcb(transport, &req)?;
+	buf.copy_from_slice(
+		&transport.ghcb().shared_buffer()[..buf.len()],
+	);
+	Ok(())
+}
+
+/// Write `data` to emulated MMIO at `gpa`.
+pub fn mmio_write<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+	data: &[u8],
+) -> Result<(), GhcbError> {
+	exchange_ghcb(transport, &MmioWriteReq::new(gpa, data)?)
+}
+
+/// Read a byte of emulated MMIO.
+pub fn mmio_read_u8<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+) -> Result<u8, GhcbError> {
+	let mut buf = [0u8; 1];
+	mmio_read(transport, gpa, &mut buf)?;
+	Ok(buf[0])
+}
+
+/// Read a little-endian `u16` of emulated MMIO.
+pub fn mmio_read_u16<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+) -> Result<u16, GhcbError> {
+	let mut buf = [0u8; 2];
+	mmio_read(transport, gpa, &mut buf)?;
+	Ok(u16::from_le_bytes(buf))
+}
+
+/// Read a little-endian `u32` of emulated MMIO.
+pub fn mmio_read_u32<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+) -> Result<u32, GhcbError> {
+	let mut buf = [0u8; 4];
+	mmio_read(transport, gpa, &mut buf)?;
+	Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a little-endian `u64` of emulated MMIO.
+pub fn mmio_read_u64<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+) -> Result<u64, GhcbError> {
+	let mut buf = [0u8; 8];
+	mmio_read(transport, gpa, &mut buf)?;
+	Ok(u64::from_le_bytes(buf))
+}
+
+/// Write a byte of emulated MMIO.
+pub fn mmio_write_u8<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+	val: u8,
+) -> Result<(), GhcbError> {
+	mmio_write(transport, gpa, &[val])
+}
+
+/// Write a little-endian `u16` of emulated MMIO.
+pub fn mmio_write_u16<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+	val: u16,
+) -> Result<(), GhcbError> {
+	mmio_write(transport, gpa, &val.to_le_bytes())
+}
+
+/// Write a little-endian `u32` of emulated MMIO.
+pub fn mmio_write_u32<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+	val: u32,
+) -> Result<(), GhcbError> {
+	mmio_write(transport, gpa, &val.to_le_bytes())
+}
+
+/// Write a little-endian `u64` of emulated MMIO.
+pub fn mmio_write_u64<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	gpa: Gpa,
+	val: u64,
+) -> Result<(), GhcbError> {
+	mmio_write(transport, gpa, &val.to_le_bytes())
+}
@@ -0,0 +1,664 @@
+use crate::addr::{Gfn, Gpa};
+use crate::ap_reset_hold::{ApResetHoldReq, ApResetHoldResp};
+use crate::cpuid::{CpuidReg, CpuidReq, CpuidResp};
+use crate::exit_info::{EventInjection, EventType, GhcbErrorReason};
+use crate::feature_support::{
+	FeatureSupportReq, FeatureSupportResp, HvFeatures,
+};
+use crate::ghcb::{Ghcb, SHARED_BUFFER_OFFSET, SHARED_BUFFER_SIZE};
+use crate::ioio::{IoDirection, IoioInfo};
+use crate::nae::SwExitCode;
+use crate::page_state::{PageOp, PageStateReq, PageStateResp};
+use crate::pref_ghcb::{PrefGhcbGpaReq, PrefGhcbGpaResp};
+use crate::psc::{
+	PageSize, PscBuffer, PSC_ERROR_INVALID_ENTRY,
+	PSC_ERROR_INVALID_HEADER,
+};
+use crate::register_ghcb::{RegisterGhcbReq, RegisterGhcbResp};
+use crate::run_vmpl::{RunVmplReq, RunVmplResp};
+use crate::sev_info::{SevInfoReq, SevInfoResp};
+use crate::termination::TerminationReq;
+use crate::transport::{GhcbMsrTransport, GhcbTransport};
+use crate::{parse_msr, GhcbMsrInfo, GhcbMsrRespEncode};
+use core::ops::Range;
+
+/// Maximum number of vCPUs tracked by a [`MockHypervisor`].
+pub const MAX_VCPUS: usize = 8;
+
+/// Maximum number of page state changes recorded by a
+/// [`MockHypervisor`]. Further requests are still answered, but not
+/// recorded.
+pub const MAX_PAGE_STATES: usize = 64;
+
+/// Maximum number of PSC entries processed by a [`MockHypervisor`]
+/// per `SNP_PSC` event, so that guests have to resume the request.
+pub const PSC_BATCH: usize = 8;
+
+/// Size of the emulated MMIO region of a [`MockHypervisor`].
+pub const MMIO_SIZE: usize = 256;
+
+/// Maximum number of port writes recorded by a [`MockHypervisor`].
+/// Further writes are discarded.
+pub const MAX_IO_WRITES: usize = 256;
+
+/// Maximum number of MSRs emulated by a [`MockHypervisor`].
+pub const MAX_MSRS: usize = 16;
+
+/// Maximum number of input ports emulated by a [`MockHypervisor`].
+pub const MAX_PORTS: usize = 16;
+
+/// Error code returned by a [`MockHypervisor`] for failed page state
+/// changes and VMPL switches.
+const MOCK_ERROR_CODE: u32 = 1;
+
+/// A CPUID function served by a [`MockHypervisor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockCpuidEntry {
+	/// CPUID function.
+	pub function: u32,
+	/// CPUID subfunction. Only taken into account for GHCB
+	/// requests, as the MSR protocol cannot pass it.
+	pub index: u32,
+	/// Values for EAX, EBX, ECX and EDX, in that order.
+	pub regs: [u32; 4],
+}
+
+/// Ways in which a [`MockHypervisor`] can be told to misbehave on
+/// its next response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+	/// Respond with an unknown response code.
+	InvalidInfo,
+	/// Respond with a known response code that does not match the
+	/// request.
+	MismatchedInfo,
+	/// Set a reserved bit in the response data, which CPUID, page
+	/// state change and VMPL responses reject with
+	/// [`InvalidData`](crate::GhcbMsrError::InvalidData). AP reset
+	/// hold responses get zero data instead, which is rejected the
+	/// same way. SEV information responses ignore their reserved
+	/// bits, and the other responses have none and are left
+	/// unchanged.
+	ReservedBits,
+	/// Register a GFN different from the requested one.
+	MismatchedGfn,
+	/// Return a CPUID register different from the requested one.
+	MismatchedReg,
+	/// Change `SW_SCRATCH` in the GHCB.
+	MovedScratch,
+	/// Fail the next `SNP_PSC` event.
+	FailPageState,
+}
+
+/// An in-memory hypervisor implementing the host side of the MSR
+/// protocol, meant for testing guest code without SEV-ES hardware.
+///
+/// The mock acts as its own [`GhcbMsrTransport`]: requests written to
+/// the MSR are answered on [`GhcbMsrTransport::vmgexit()`]. It also
+/// holds a GHCB page and acts as a [`GhcbTransport`], serving
+/// non-automatic exit events when the registered GHCB GPA is written
+/// to the MSR.
+#[derive(Debug, Clone)]
+pub struct MockHypervisor<'a> {
+	msr: u64,
+	min_ver: u16,
+	max_ver: u16,
+	enc_bit_no: u8,
+	features: HvFeatures,
+	pref_gfn: Gfn,
+	cpuid: &'a [MockCpuidEntry],
+	vcpu: usize,
+	ghcb_gfns: [Option<Gfn>; MAX_VCPUS],
+	page_states: [(Gfn, PageOp); MAX_PAGE_STATES],
+	num_page_states: usize,
+	vmpl: Option<u8>,
+	termination: Option<(u8, u8)>,
+	misbehavior: Option<Misbehavior>,
+	ghcb: Ghcb,
+	io_writes: [(u16, u32); MAX_IO_WRITES],
+	num_io_writes: usize,
+	mmio_base: Gpa,
+	mmio: [u8; MMIO_SIZE],
+	msrs: [(u32, u64); MAX_MSRS],
+	num_msrs: usize,
+	ports: [(u16, u32); MAX_PORTS],
+	num_ports: usize,
+	failing_gfn: Option<Gfn>,
+}
+
+impl<'a> MockHypervisor<'a> {
+	/// Create a mock hypervisor supporting protocol versions 1 and
+	/// 2, with the encryption bit at position 51, no features and an
+	/// empty CPUID table.
+	pub const fn new() -> Self {
+		Self {
+			msr: 0,
+			min_ver: 1,
+			max_ver: 2,
+			enc_bit_no: 51,
+			features: HvFeatures::empty(),
+			pref_gfn: Gfn::new(0),
+			cpuid: &[],
+			vcpu: 0,
+			ghcb_gfns: [None; MAX_VCPUS],
+			page_states: [(Gfn::new(0), PageOp::Private);
+				MAX_PAGE_STATES],
+			num_page_states: 0,
+			vmpl: None,
+			termination: None,
+			misbehavior: None,
+			ghcb: Ghcb::new(),
+			io_writes: [(0, 0); MAX_IO_WRITES],
+			num_io_writes: 0,
+			mmio_base: Gpa::new(0),
+			mmio: [0; MMIO_SIZE],
+			msrs: [(0, 0); MAX_MSRS],
+			num_msrs: 0,
+			ports: [(0, 0); MAX_PORTS],
+			num_ports: 0,
+			failing_gfn: None,
+		}
+	}
+
+	/// Set the supported protocol version range.
+	pub const fn with_versions(mut self, min: u16, max: u16) -> Self {
+		self.min_ver = min;
+		self.max_ver = max;
+		self
+	}
+
+	/// Set the position of the encryption bit (C-bit).
+	pub const fn with_enc_bit(mut self, enc_bit_no: u8) -> Self {
+		self.enc_bit_no = enc_bit_no;
+		self
+	}
+
+	/// Set the hypervisor feature bitmap.
+	pub const fn with_features(
+		mut self,
+		features: HvFeatures,
+	) -> Self {
+		self.features = features;
+		self
+	}
+
+	/// Set the preferred GHCB GFN.
+	pub const fn with_pref_gfn(mut self, gfn: Gfn) -> Self {
+		self.pref_gfn = gfn;
+		self
+	}
+
+	/// Set the CPUID table. Functions not present in the table read
+	/// as zero.
+	pub const fn with_cpuid(
+		mut self,
+		cpuid: &'a [MockCpuidEntry],
+	) -> Self {
+		self.cpuid = cpuid;
+		self
+	}
+
+	/// Fail every page state change of the given GFN, through either
+	/// the MSR protocol or the GHCB.
+	pub const fn with_failing_gfn(mut self, gfn: Gfn) -> Self {
+		self.failing_gfn = Some(gfn);
+		self
+	}
+
+	/// Set the GPA of the emulated MMIO region, of size
+	/// [`MMIO_SIZE`].
+	pub const fn with_mmio_base(mut self, gpa: Gpa) -> Self {
+		self.mmio_base = gpa;
+		self
+	}
+
+	/// The contents of the emulated MMIO region.
+	pub fn mmio(&mut self) -> &mut [u8; MMIO_SIZE] {
+		&mut self.mmio
+	}
+
+	/// Set the value of an emulated MSR, adding it if needed. Reads
+	/// of MSRs that were never set or written inject `#GP(0)`.
+	///
+	/// # Panics
+	///
+	/// Panics if more than [`MAX_MSRS`] MSRs are added.
+	pub fn set_msr(&mut self, msr: u32, val: u64) {
+		match self.msrs[..self.num_msrs]
+			.iter_mut()
+			.find(|e| e.0 == msr)
+		{
+			Some(entry) => entry.1 = val,
+			None => {
+				self.msrs[self.num_msrs] = (msr, val);
+				self.num_msrs += 1;
+			}
+		}
+	}
+
+	/// The value of an emulated MSR, if it has been set.
+	pub fn msr_value(&self, msr: u32) -> Option<u64> {
+		self.msrs[..self.num_msrs]
+			.iter()
+			.find(|e| e.0 == msr)
+			.map(|e| e.1)
+	}
+
+	/// Set the value read by `IN` events from an emulated port.
+	///
+	/// # Panics
+	///
+	/// Panics if more than [`MAX_PORTS`] ports are added.
+	pub fn set_port(&mut self, port: u16, val: u32) {
+		match self.ports[..self.num_ports]
+			.iter_mut()
+			.find(|e| e.0 == port)
+		{
+			Some(entry) => entry.1 = val,
+			None => {
+				self.ports[self.num_ports] = (port, val);
+				self.num_ports += 1;
+			}
+		}
+	}
+
+	/// Select the vCPU issuing subsequent requests.
+	///
+	/// # Panics
+	///
+	/// Panics if `vcpu` is not lower than [`MAX_VCPUS`].
+	pub fn set_vcpu(&mut self, vcpu: usize) {
+		assert!(vcpu < MAX_VCPUS);
+		self.vcpu = vcpu;
+	}
+
+	/// Misbehave in the specified way on the next response. If the
+	/// misbehavior does not apply to the next request, it is
+	/// discarded.
+	pub fn misbehave(&mut self, misbehavior: Misbehavior) {
+		self.misbehavior = Some(misbehavior);
+	}
+
+	/// The GHCB GFN registered by the given vCPU, if any.
+	pub fn registered_gfn(&self, vcpu: usize) -> Option<Gfn> {
+		self.ghcb_gfns.get(vcpu).copied().flatten()
+	}
+
+	/// The page state changes requested so far, in order.
+	pub fn page_states(&self) -> &[(Gfn, PageOp)] {
+		&self.page_states[..self.num_page_states]
+	}
+
+	/// The port and value of the `OUT` events issued so far, in
+	/// order. `IN` events read the value set with
+	/// [`Self::set_port()`], or zero.
+	pub fn io_writes(&self) -> &[(u16, u32)] {
+		&self.io_writes[..self.num_io_writes]
+	}
+
+	/// The last VMPL the guest requested to run at, if any.
+	pub fn vmpl(&self) -> Option<u8> {
+		self.vmpl
+	}
+
+	/// The code set and reason of the guest's termination request,
+	/// if any.
+	pub fn termination(&self) -> Option<(u8, u8)> {
+		self.termination
+	}
+
+	fn cpuid_value(&self, function: u32, reg: CpuidReg) -> u32 {
+		self.cpuid
+			.iter()
+			.find(|e| e.function == function)
+			.map_or(0, |e| e.regs[reg as usize])
+	}
+
+	fn cpuid_regs(&self, function: u32, index: u32) -> [u32; 4] {
+		self.cpuid
+			.iter()
+			.find(|e| e.function == function && e.index == index)
+			.map_or([0; 4], |e| e.regs)
+	}
+
+	fn record_page_state(&mut self, gfn: Gfn, op: PageOp) {
+		if self.num_page_states < MAX_PAGE_STATES {
+			self.page_states[self.num_page_states] = (gfn, op);
+			self.num_page_states += 1;
+		}
+	}
+
+	/// Process up to [`PSC_BATCH`] entries of the PSC buffer in the
+	/// shared buffer, returning the value of `SW_EXITINFO2`.
+	fn page_state_change(&mut self) -> u64 {
+		let buf = self.ghcb.shared_buffer();
+		let Ok(mut psc) = PscBuffer::from_bytes(buf) else {
+			return PSC_ERROR_INVALID_HEADER;
+		};
+		if self.misbehavior == Some(Misbehavior::FailPageState) {
+			self.misbehavior = None;
+			return 0x16;
+		}
+
+		for _ in 0..PSC_BATCH {
+			let Some(entry) =
+				psc.entries().get(psc.cur_entry() as usize).copied()
+			else {
+				break;
+			};
+			let large = entry.size() == PageSize::Size2M
+				|| entry.op().page_op().is_none();
+			if large && !entry.gfn().is_2m_aligned() {
+				psc.write_bytes(self.ghcb.shared_buffer_mut());
+				return PSC_ERROR_INVALID_ENTRY;
+			}
+			let pages =
+				entry.gfn()..entry.gfn() + entry.size().pages();
+			if self
+				.failing_gfn
+				.is_some_and(|gfn| pages.contains(&gfn))
+			{
+				psc.write_bytes(self.ghcb.shared_buffer_mut());
+				return 0x16;
+			}
+			if let Some(op) = entry.op().page_op() {
+				self.record_page_state(entry.gfn(), op);
+			}
+			psc.complete_entry();
+		}
+		psc.write_bytes(self.ghcb.shared_buffer_mut());
+		0
+	}
+
+	fn ghcb_error(&mut self, reason: GhcbErrorReason) {
+		self.ghcb.set_sw_exit_info_1(2);
+		self.ghcb.set_sw_exit_info_2(reason.code());
+	}
+
+	fn inject_gp(&mut self) {
+		let gp = EventInjection::new(13, EventType::Exception)
+			.with_error_code(0);
+		self.ghcb.set_sw_exit_info_1(1);
+		self.ghcb.set_sw_exit_info_2(gp.encode());
+	}
+
+	/// The range of the MMIO region targeted by an MMIO event, if the
+	/// event is valid.
+	fn mmio_range(&self) -> Option<Range<usize>> {
+		let scratch = self.ghcb_gpa() + SHARED_BUFFER_OFFSET as u64;
+		if self.ghcb.sw_scratch() != scratch.as_u64() {
+			return None;
+		}
+		let len = self.ghcb.sw_exit_info_2() as usize;
+		let start = self
+			.ghcb
+			.sw_exit_info_1()
+			.checked_sub(self.mmio_base.as_u64())?;
+		let start = usize::try_from(start).ok()?;
+		let end = start.checked_add(len)?;
+		if len > SHARED_BUFFER_SIZE || end > MMIO_SIZE {
+			return None;
+		}
+		Some(start..end)
+	}
+
+	fn handle_ghcb(&mut self) {
+		let code = SwExitCode::try_from(self.ghcb.sw_exit_code());
+		match code {
+			Ok(SwExitCode::CPUID) => {
+				let function = self.ghcb.rax() as u32;
+				let index = self.ghcb.rcx() as u32;
+				let regs = self.cpuid_regs(function, index);
+				self.ghcb.set_rax(regs[0] as u64);
+				self.ghcb.set_rbx(regs[1] as u64);
+				self.ghcb.set_rcx(regs[2] as u64);
+				self.ghcb.set_rdx(regs[3] as u64);
+				self.ghcb.set_sw_exit_info_1(0);
+			}
+			Ok(SwExitCode::IOIO) => {
+				let Ok(info) =
+					IoioInfo::try_from(self.ghcb.sw_exit_info_1())
+				else {
+					return self
+						.ghcb_error(GhcbErrorReason::InvalidInput);
+				};
+				if info.dir() == IoDirection::In {
+					let val = self.ports[..self.num_ports]
+						.iter()
+						.find(|e| e.0 == info.port())
+						.map_or(0, |e| e.1);
+					self.ghcb.set_rax(val as u64);
+				} else if self.num_io_writes < MAX_IO_WRITES {
+					let val = self.ghcb.rax() as u32;
+					self.io_writes[self.num_io_writes] =
+						(info.port(), val);
+					self.num_io_writes += 1;
+				}
+				self.ghcb.set_sw_exit_info_1(0);
+			}
+			Ok(SwExitCode::MMIO_READ) => {
+				let Some(range) = self.mmio_range() else {
+					return self
+						.ghcb_error(GhcbErrorReason::InvalidInput);
+				};
+				let len = range.len();
+				self.ghcb.shared_buffer_mut()[..len]
+					.copy_from_slice(&self.mmio[range]);
+				self.ghcb.set_sw_exit_info_1(0);
+			}
+			Ok(SwExitCode::MMIO_WRITE) => {
+				let Some(range) = self.mmio_range() else {
+					return self
+						.ghcb_error(GhcbErrorReason::InvalidInput);
+				};
+				let len = range.len();
+				self.mmio[range].copy_from_slice(
+					&self.ghcb.shared_buffer()[..len],
+				);
+				self.ghcb.set_sw_exit_info_1(0);
+			}
+			Ok(SwExitCode::MSR) => {
+				let msr = self.ghcb.rcx() as u32;
+				match self.ghcb.sw_exit_info_1() {
+					0 => {
+						let Some(val) = self.msr_value(msr) else {
+							return self.inject_gp();
+						};
+						self.ghcb.set_rax(val & 0xffffffff);
+						self.ghcb.set_rdx(val >> 32);
+					}
+					1 if self.num_msrs < MAX_MSRS
+						|| self.msr_value(msr).is_some() =>
+					{
+						let lo = self.ghcb.rax() & 0xffffffff;
+						let hi = self.ghcb.rdx() & 0xffffffff;
+						self.set_msr(msr, hi << 32 | lo);
+					}
+					1 => return self.inject_gp(),
+					_ => {
+						return self.ghcb_error(
+							GhcbErrorReason::InvalidInput,
+						)
+					}
+				}
+				self.ghcb.set_sw_exit_info_1(0);
+			}
+			Ok(SwExitCode::SNP_PSC) => {
+				let scratch =
+					self.ghcb_gpa() + SHARED_BUFFER_OFFSET as u64;
+				if self.ghcb.sw_scratch() != scratch.as_u64() {
+					return self.ghcb_error(
+						GhcbErrorReason::InvalidScratchArea,
+					);
+				}
+				let info_2 = self.page_state_change();
+				self.ghcb.set_sw_exit_info_1(0);
+				self.ghcb.set_sw_exit_info_2(info_2);
+			}
+			// Signal an invalid request
+			_ => self.ghcb_error(GhcbErrorReason::InvalidEvent),
+		}
+		if self.misbehavior == Some(Misbehavior::MovedScratch) {
+			self.misbehavior = None;
+			let scratch = self.ghcb.sw_scratch();
+			self.ghcb.set_sw_scratch(scratch.wrapping_add(8));
+		}
+	}
+
+	fn respond<R: GhcbMsrRespEncode>(&mut self, resp: R) {
+		let mut info = resp.info() as u64;
+		let mut data = resp.data();
+		match self.misbehavior.take() {
+			Some(Misbehavior::InvalidInfo) => info = 0xfff,
+			Some(Misbehavior::MismatchedInfo) => {
+				info = if info == GhcbMsrInfo::SEV_INFO_RESP as u64 {
+					GhcbMsrInfo::CPUID_RESP as u64
+				} else {
+					GhcbMsrInfo::SEV_INFO_RESP as u64
+				}
+			}
+			Some(Misbehavior::ReservedBits) => match resp.info() {
+				GhcbMsrInfo::AP_RESET_HOLD_RESP => data = 0,
+				GhcbMsrInfo::SEV_INFO_RESP
+				| GhcbMsrInfo::CPUID_RESP
+				| GhcbMsrInfo::STATE_CHANGE_RESP
+				| GhcbMsrInfo::RUN_VMPL_RESP => data |= 1,
+				_ => (),
+			},
+			Some(Misbehavior::MismatchedGfn)
+				if info == GhcbMsrInfo::REG_GHCB_GPA_RESP as u64 =>
+			{
+				data = data.wrapping_add(1);
+			}
+			Some(Misbehavior::MismatchedReg)
+				if info == GhcbMsrInfo::CPUID_RESP as u64 =>
+			{
+				data ^= 1 << 18;
+			}
+			Some(Misbehavior::MismatchedGfn)
+			| Some(Misbehavior::MismatchedReg)
+			| Some(Misbehavior::MovedScratch)
+			| Some(Misbehavior::FailPageState)
+			| None => (),
+		}
+		self.msr = (data << 12) | info;
+	}
+
+	fn handle(&mut self) {
+		let msr = self.msr;
+		let Ok(info) = GhcbMsrInfo::try_from(parse_msr(msr).0) else {
+			return;
+		};
+		match info {
+			GhcbMsrInfo::SEV_INFO_REQ => {
+				if SevInfoReq::try_from(msr).is_err() {
+					return;
+				}
+				self.respond(SevInfoResp::new(
+					self.min_ver,
+					self.max_ver,
+					self.enc_bit_no,
+				));
+			}
+			GhcbMsrInfo::CPUID_REQ => {
+				let Ok(req) = CpuidReq::try_from(msr) else {
+					return;
+				};
+				let value =
+					self.cpuid_value(req.function(), req.reg());
+				self.respond(CpuidResp::new(value, req.reg()));
+			}
+			GhcbMsrInfo::AP_RESET_HOLD_REQ => {
+				if ApResetHoldReq::try_from(msr).is_err() {
+					return;
+				}
+				self.respond(ApResetHoldResp::new());
+			}
+			GhcbMsrInfo::PREF_GHCB_GPA_REQ => {
+				if PrefGhcbGpaReq::try_from(msr).is_err() {
+					return;
+				}
+				self.respond(PrefGhcbGpaResp::new(self.pref_gfn));
+			}
+			GhcbMsrInfo::REG_GHCB_GPA_REQ => {
+				let Ok(req) = RegisterGhcbReq::try_from(msr) else {
+					return;
+				};
+				self.ghcb_gfns[self.vcpu] = Some(req.gfn());
+				self.respond(RegisterGhcbResp::for_gfn(req.gfn()));
+			}
+			GhcbMsrInfo::STATE_CHANGE_REQ => {
+				let Ok(req) = PageStateReq::try_from(msr) else {
+					let code = MOCK_ERROR_CODE;
+					self.respond(PageStateResp::with_error(code));
+					return;
+				};
+				if self.failing_gfn == Some(req.gfn()) {
+					let code = MOCK_ERROR_CODE;
+					self.respond(PageStateResp::with_error(code));
+					return;
+				}
+				self.record_page_state(req.gfn(), req.op());
+				self.respond(PageStateResp::new());
+			}
+			GhcbMsrInfo::RUN_VMPL_REQ => {
+				let Ok(req) = RunVmplReq::try_from(msr) else {
+					let code = MOCK_ERROR_CODE;
+					self.respond(RunVmplResp::with_error(code));
+					return;
+				};
+				self.vmpl = Some(req.vmpl());
+				self.respond(RunVmplResp::new());
+			}
+			GhcbMsrInfo::FEAT_SUPPORT_REQ => {
+				if FeatureSupportReq::try_from(msr).is_err() {
+					return;
+				}
+				self.respond(FeatureSupportResp::new(self.features));
+			}
+			GhcbMsrInfo::TERM_REQ => {
+				if let Ok(req) = TerminationReq::try_from(msr) {
+					self.termination =
+						Some((req.code_set(), req.reason()));
+				}
+			}
+			// Responses and GHCB GPAs are not valid requests; leave
+			// the MSR untouched.
+			_ => (),
+		}
+	}
+}
+
+impl Default for MockHypervisor<'_> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl GhcbMsrTransport for MockHypervisor<'_> {
+	fn write_msr(&mut self, val: u64) {
+		self.msr = val;
+	}
+	fn read_msr(&mut self) -> u64 {
+		self.msr
+	}
+	fn vmgexit(&mut self) {
+		match self.registered_gfn(self.vcpu) {
+			Some(gfn) if gfn.gpa() == Some(Gpa::new(self.msr)) => {
+				self.handle_ghcb()
+			}
+			_ => self.handle(),
+		}
+	}
+}
+
+impl GhcbTransport for MockHypervisor<'_> {
+	fn ghcb(&mut self) -> &mut Ghcb {
+		&mut self.ghcb
+	}
+	fn ghcb_gpa(&self) -> Gpa {
+		self.registered_gfn(self.vcpu)
+			.and_then(|gfn| gfn.gpa())
+			.unwrap_or_default()
+	}
+}
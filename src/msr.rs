@@ -0,0 +1,97 @@
+use crate::addr::Gpa;
+use crate::ghcb::{Ghcb, GhcbError, GhcbField, GhcbRequest};
+use crate::nae::SwExitCode;
+use crate::transport::{exchange_ghcb, GhcbTransport};
+
+/// A request to read an MSR (`RDMSR`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsrReadReq {
+	msr: u32,
+}
+
+impl MsrReadReq {
+	pub const fn new(msr: u32) -> Self {
+		Self { msr }
+	}
+
+	pub const fn msr(&self) -> u32 {
+		self.msr
+	}
+}
+
+impl GhcbRequest for MsrReadReq {
+	type Resp = u64;
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::MSR
+	}
+	fn prepare(&self, ghcb: &mut Ghcb, _ghcb_gpa: Gpa) {
+		ghcb.set_rcx(self.msr as u64);
+	}
+	fn response(&self, ghcb: &Ghcb) -> Result<Self::Resp, GhcbError> {
+		let lo = ghcb.get_valid(GhcbField::Rax)? & 0xffffffff;
+		let hi = ghcb.get_valid(GhcbField::Rdx)? & 0xffffffff;
+		Ok(hi << 32 | lo)
+	}
+}
+
+/// A request to write a value to an MSR (`WRMSR`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsrWriteReq {
+	msr: u32,
+	value: u64,
+}
+
+impl MsrWriteReq {
+	pub const fn new(msr: u32, value: u64) -> Self {
+		Self { msr, value }
+	}
+
+	pub const fn msr(&self) -> u32 {
+		self.msr
+	}
+
+	pub const fn value(&self) -> u64 {
+		self.value
+	}
+}
+
+impl GhcbRequest for MsrWriteReq {
+	type Resp = ();
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::MSR
+	}
+	fn exit_info_1(&self) -> u64 {
+		1
+	}
+	fn prepare(&self, ghcb: &mut Ghcb, _ghcb_gpa: Gpa) {
+		ghcb.set_rcx(self.msr as u64);
+		ghcb.set_rax(self.value & 0xffffffff);
+		ghcb.set_rdx(self.value >> 32);
+	}
+	fn response(
+		&self,
+		_ghcb: &Ghcb,
+	) -> Result<Self::Resp, GhcbError> {
+		Ok(())
+	}
+}
+
+/// Read an MSR through the GHCB. If the hypervisor asks to inject an
+/// exception (usually `#GP` for an unknown MSR), the call fails with
+/// [`GhcbError::EventInjection`].
+pub fn read_msr<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	msr: u32,
+) -> Result<u64, GhcbError> {
+	exchange_ghcb(transport, &MsrReadReq::new(msr))
+}
+
+/// Write an MSR through the GHCB. See [`read_msr()`] for error
+/// handling.
+pub fn write_msr<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	msr: u32,
+	value: u64,
+) -> Result<(), GhcbError> {
+	exchange_ghcb(transport, &MsrWriteReq::new(msr, value))
+}
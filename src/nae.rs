@@ -0,0 +1,202 @@
+use crate::ghcb::{GhcbError, GhcbField};
+use core::fmt;
+
+use GhcbField::*;
+
+/// Exit codes for non-automatic exit (NAE) events, written to the
+/// `SW_EXITCODE` field of the GHCB. These are either SVM exit codes
+/// or GHCB-specific `VMGEXIT` codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+#[allow(non_camel_case_types)]
+pub enum SwExitCode {
+	DR7_READ = 0x27,
+	DR7_WRITE = 0x37,
+	RDTSC = 0x6e,
+	RDPMC = 0x6f,
+	CPUID = 0x72,
+	INVD = 0x76,
+	IOIO = 0x7b,
+	MSR = 0x7c,
+	VMMCALL = 0x81,
+	RDTSCP = 0x87,
+	WBINVD = 0x89,
+	MONITOR = 0x8a,
+	MWAIT = 0x8b,
+	MMIO_READ = 0x8000_0001,
+	MMIO_WRITE = 0x8000_0002,
+	NMI_COMPLETE = 0x8000_0003,
+	AP_HLT_LOOP = 0x8000_0004,
+	AP_JUMP_TABLE = 0x8000_0005,
+	SNP_PSC = 0x8000_0010,
+	SNP_GUEST_REQUEST = 0x8000_0011,
+	SNP_EXT_GUEST_REQUEST = 0x8000_0012,
+	SNP_AP_CREATION = 0x8000_0013,
+	HV_DOORBELL_PAGE = 0x8000_0014,
+	HV_IPI = 0x8000_0015,
+	HV_TIMER = 0x8000_0016,
+	GET_APIC_IDS = 0x8000_0017,
+	SNP_RUN_VMPL = 0x8000_0018,
+	TERM_REQUEST = 0x8000_fffe,
+	UNSUPPORTED_EVENT = 0x8000_ffff,
+}
+
+impl SwExitCode {
+	const ALL: [Self; 29] = [
+		Self::DR7_READ,
+		Self::DR7_WRITE,
+		Self::RDTSC,
+		Self::RDPMC,
+		Self::CPUID,
+		Self::INVD,
+		Self::IOIO,
+		Self::MSR,
+		Self::VMMCALL,
+		Self::RDTSCP,
+		Self::WBINVD,
+		Self::MONITOR,
+		Self::MWAIT,
+		Self::MMIO_READ,
+		Self::MMIO_WRITE,
+		Self::NMI_COMPLETE,
+		Self::AP_HLT_LOOP,
+		Self::AP_JUMP_TABLE,
+		Self::SNP_PSC,
+		Self::SNP_GUEST_REQUEST,
+		Self::SNP_EXT_GUEST_REQUEST,
+		Self::SNP_AP_CREATION,
+		Self::HV_DOORBELL_PAGE,
+		Self::HV_IPI,
+		Self::HV_TIMER,
+		Self::GET_APIC_IDS,
+		Self::SNP_RUN_VMPL,
+		Self::TERM_REQUEST,
+		Self::UNSUPPORTED_EVENT,
+	];
+
+	/// Save area fields the guest may need to provide for the event,
+	/// besides `SW_EXITCODE`, `SW_EXITINFO1` and `SW_EXITINFO2`.
+	/// Some are only required in certain cases (e.g. `RAX` for
+	/// `IOIO` is only an input for `OUT`).
+	pub const fn inputs(&self) -> &'static [GhcbField] {
+		match self {
+			Self::CPUID => &[Rax, Rcx, Xcr0, Xss],
+			Self::IOIO => &[Rax, SwScratch],
+			Self::MSR => &[Rax, Rcx, Rdx],
+			Self::VMMCALL => &[Rax, Cpl],
+			Self::RDPMC => &[Rcx],
+			Self::MONITOR => &[Rax, Rcx, Rdx],
+			Self::MWAIT => &[Rax, Rcx],
+			Self::DR7_WRITE => &[Rax],
+			Self::MMIO_READ | Self::MMIO_WRITE | Self::SNP_PSC => {
+				&[SwScratch]
+			}
+			Self::SNP_EXT_GUEST_REQUEST => &[Rax, Rbx],
+			Self::SNP_AP_CREATION | Self::GET_APIC_IDS => &[Rax],
+			Self::DR7_READ
+			| Self::RDTSC
+			| Self::INVD
+			| Self::RDTSCP
+			| Self::WBINVD
+			| Self::NMI_COMPLETE
+			| Self::AP_HLT_LOOP
+			| Self::AP_JUMP_TABLE
+			| Self::SNP_GUEST_REQUEST
+			| Self::HV_DOORBELL_PAGE
+			| Self::HV_IPI
+			| Self::HV_TIMER
+			| Self::SNP_RUN_VMPL
+			| Self::TERM_REQUEST
+			| Self::UNSUPPORTED_EVENT => &[],
+		}
+	}
+
+	/// Save area fields the hypervisor must mark as valid on
+	/// return, besides `SW_EXITINFO1` and `SW_EXITINFO2`. Some are
+	/// only returned in certain cases (e.g. `RAX` for `IOIO` is only
+	/// an output for `IN`).
+	pub const fn outputs(&self) -> &'static [GhcbField] {
+		match self {
+			Self::CPUID => &[Rax, Rbx, Rcx, Rdx],
+			Self::IOIO
+			| Self::VMMCALL
+			| Self::DR7_READ
+			| Self::GET_APIC_IDS => &[Rax],
+			Self::MSR | Self::RDTSC | Self::RDPMC => &[Rax, Rdx],
+			Self::RDTSCP => &[Rax, Rcx, Rdx],
+			Self::SNP_EXT_GUEST_REQUEST => &[Rbx],
+			Self::DR7_WRITE
+			| Self::INVD
+			| Self::WBINVD
+			| Self::MONITOR
+			| Self::MWAIT
+			| Self::MMIO_READ
+			| Self::MMIO_WRITE
+			| Self::NMI_COMPLETE
+			| Self::AP_HLT_LOOP
+			| Self::AP_JUMP_TABLE
+			| Self::SNP_PSC
+			| Self::SNP_GUEST_REQUEST
+			| Self::SNP_AP_CREATION
+			| Self::HV_DOORBELL_PAGE
+			| Self::HV_IPI
+			| Self::HV_TIMER
+			| Self::SNP_RUN_VMPL
+			| Self::TERM_REQUEST
+			| Self::UNSUPPORTED_EVENT => &[],
+		}
+	}
+
+	/// A human readable name for the event.
+	pub const fn name(&self) -> &'static str {
+		match self {
+			Self::DR7_READ => "DR7 read",
+			Self::DR7_WRITE => "DR7 write",
+			Self::RDTSC => "RDTSC",
+			Self::RDPMC => "RDPMC",
+			Self::CPUID => "CPUID",
+			Self::INVD => "INVD",
+			Self::IOIO => "IOIO",
+			Self::MSR => "MSR",
+			Self::VMMCALL => "VMMCALL",
+			Self::RDTSCP => "RDTSCP",
+			Self::WBINVD => "WBINVD",
+			Self::MONITOR => "MONITOR",
+			Self::MWAIT => "MWAIT",
+			Self::MMIO_READ => "MMIO read",
+			Self::MMIO_WRITE => "MMIO write",
+			Self::NMI_COMPLETE => "NMI complete",
+			Self::AP_HLT_LOOP => "AP HLT loop",
+			Self::AP_JUMP_TABLE => "AP jump table",
+			Self::SNP_PSC => "SNP page state change",
+			Self::SNP_GUEST_REQUEST => "SNP guest request",
+			Self::SNP_EXT_GUEST_REQUEST => {
+				"SNP extended guest request"
+			}
+			Self::SNP_AP_CREATION => "SNP AP creation",
+			Self::HV_DOORBELL_PAGE => "hypervisor doorbell page",
+			Self::HV_IPI => "hypervisor IPI",
+			Self::HV_TIMER => "hypervisor timer",
+			Self::GET_APIC_IDS => "APIC ID list",
+			Self::SNP_RUN_VMPL => "SNP run VMPL",
+			Self::TERM_REQUEST => "termination request",
+			Self::UNSUPPORTED_EVENT => "unsupported event",
+		}
+	}
+}
+
+impl TryFrom<u64> for SwExitCode {
+	type Error = GhcbError;
+	fn try_from(val: u64) -> Result<Self, Self::Error> {
+		Self::ALL
+			.into_iter()
+			.find(|code| *code as u64 == val)
+			.ok_or(GhcbError::InvalidExitCode(val))
+	}
+}
+
+impl fmt::Display for SwExitCode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} ({:#x})", self.name(), *self as u64)
+	}
+}
@@ -1,5 +1,8 @@
+use crate::addr::Gfn;
+use crate::transport::{exchange, GhcbMsrTransport};
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
 
 /// The state that the page will be set to after a successful
@@ -11,40 +14,116 @@ pub enum PageOp {
 	Shared = 2,
 }
 
+impl TryFrom<u8> for PageOp {
+	type Error = GhcbMsrError;
+	fn try_from(val: u8) -> Result<Self, Self::Error> {
+		match val {
+			v if v == Self::Private as u8 => Ok(Self::Private),
+			v if v == Self::Shared as u8 => Ok(Self::Shared),
+			_ => Err(GhcbMsrError::InvalidData),
+		}
+	}
+}
+
 /// A request from the guest to change the state of a page specified
 /// with a GFN.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PageStateReq {
-	data: u64,
+	gfn: Gfn,
+	op: PageOp,
 }
 
 impl PageStateReq {
-	pub const fn new(gfn: u64, op: PageOp) -> Self {
-		let op = op as u64;
-		Self {
-			data: (op << 40) | gfn,
+	/// The GFN is not checked, and overwrites the operation when
+	/// encoded if wider than 40 bits. See [`Self::try_new()`].
+	pub const fn new(gfn: Gfn, op: PageOp) -> Self {
+		Self { gfn, op }
+	}
+
+	/// Like [`Self::new()`], but fails if `gfn` does not fit in 40
+	/// bits.
+	pub const fn try_new(
+		gfn: Gfn,
+		op: PageOp,
+	) -> Result<Self, GhcbMsrError> {
+		if gfn.as_u64() >> 40 != 0 {
+			return Err(GhcbMsrError::OutOfRange(gfn.as_u64()));
 		}
+		Ok(Self::new(gfn, op))
+	}
+
+	/// The GFN of the page to change.
+	pub const fn gfn(&self) -> Gfn {
+		self.gfn
+	}
+
+	/// The requested page state.
+	pub const fn op(&self) -> PageOp {
+		self.op
+	}
+}
+
+impl TryFrom<u64> for PageStateReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::STATE_CHANGE_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data >> 44 != 0 {
+			return Err(GhcbMsrError::InvalidData);
+		}
+		let gfn = Gfn::new(data & 0xffffffffff);
+		let op = PageOp::try_from(((data >> 40) & 0xf) as u8)?;
+		Ok(Self::new(gfn, op))
 	}
 }
 
 impl GhcbMsrRequest for PageStateReq {
 	type Resp = PageStateResp;
 	fn data(&self) -> u64 {
-		self.data
+		let op = self.op as u64;
+		(op << 40) | self.gfn.as_u64()
 	}
 	fn info(&self) -> GhcbMsrInfo {
 		GhcbMsrInfo::STATE_CHANGE_REQ
 	}
+	fn response(
+		&self,
+		resp: u64,
+	) -> Result<Self::Resp, GhcbMsrError> {
+		let resp = PageStateResp::try_from(resp)?;
+		match resp.error_code {
+			0 => Ok(resp),
+			code => Err(GhcbMsrError::Hypervisor(code)),
+		}
+	}
 }
 
 /// A response from the hypervisor indicating whether the page had its
 /// state changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct PageStateResp {
 	/// The error code. A non-zero value indicates an error occurred
-	/// and the page did not change state.
+	/// and the page did not change state. The code is
+	/// hypervisor-specific. [`PageStateReq::response()`] turns it
+	/// into an error.
 	pub error_code: u32,
 }
 
+impl PageStateResp {
+	/// A response indicating success.
+	pub const fn new() -> Self {
+		Self::with_error(0)
+	}
+
+	/// A response with the given error code.
+	pub const fn with_error(error_code: u32) -> Self {
+		Self { error_code }
+	}
+}
+
 impl TryFrom<u64> for PageStateResp {
 	type Error = GhcbMsrError;
 	fn try_from(resp: u64) -> Result<Self, Self::Error> {
@@ -63,3 +142,151 @@ impl TryFrom<u64> for PageStateResp {
 }
 
 impl GhcbMsrResp for PageStateResp {}
+
+impl GhcbMsrRespEncode for PageStateResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::STATE_CHANGE_RESP
+	}
+	fn data(&self) -> u64 {
+		(self.error_code as u64) << 20
+	}
+}
+
+/// Errors encountered while converting a page with
+/// [`convert_range()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionError<E> {
+	/// The request failed, including the hypervisor refusing the
+	/// state change (see [`GhcbMsrError::Hypervisor`]).
+	Msr(GhcbMsrError),
+	/// The user callback failed.
+	Callback(E),
+}
+
+/// Report of a failed [`convert_range()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversionReport<E> {
+	/// The GFN of the page that could not be converted.
+	pub failed_gfn: Gfn,
+	/// Why the page could not be converted.
+	pub error: ConversionError<E>,
+	/// The error that stopped the partial conversion of `failed_gfn`
+	/// from being undone, if any. The page is then left shared but
+	/// still validated, or private but not validated.
+	pub undo_error: Option<ConversionError<E>>,
+	/// Number of pages converted before the failure.
+	pub converted: u64,
+	/// Number of converted pages restored to their original state.
+	pub rolled_back: u64,
+	/// The GFN and error that stopped the rollback, if it did not
+	/// complete. That page may itself be left partially converted.
+	pub rollback_error: Option<(Gfn, ConversionError<E>)>,
+}
+
+impl PageOp {
+	const fn inverse(&self) -> Self {
+		match self {
+			Self::Private => Self::Shared,
+			Self::Shared => Self::Private,
+		}
+	}
+}
+
+fn request_state<T, E>(
+	transport: &mut T,
+	gfn: Gfn,
+	op: PageOp,
+) -> Result<(), ConversionError<E>>
+where
+	T: GhcbMsrTransport + ?Sized,
+{
+	let req = PageStateReq::try_new(gfn, op)
+		.map_err(ConversionError::Msr)?;
+	exchange(transport, &req).map_err(ConversionError::Msr)?;
+	Ok(())
+}
+
+/// Convert a single page, calling `f` before making it shared or
+/// after making it private. If the second step fails, the first one
+/// is undone, and the error is returned along with the error of the
+/// undo, if any.
+fn convert_page<T, E, F>(
+	transport: &mut T,
+	gfn: Gfn,
+	op: PageOp,
+	f: &mut F,
+) -> Result<(), (ConversionError<E>, Option<ConversionError<E>>)>
+where
+	T: GhcbMsrTransport + ?Sized,
+	F: FnMut(Gfn, PageOp) -> Result<(), E>,
+{
+	match op {
+		PageOp::Shared => {
+			f(gfn, op)
+				.map_err(|e| (ConversionError::Callback(e), None))?;
+			request_state(transport, gfn, op).map_err(|e| {
+				let undo = f(gfn, op.inverse());
+				(e, undo.err().map(ConversionError::Callback))
+			})
+		}
+		PageOp::Private => {
+			request_state(transport, gfn, op)
+				.map_err(|e| (e, None))?;
+			f(gfn, op).map_err(|e| {
+				let undo =
+					request_state(transport, gfn, op.inverse());
+				(ConversionError::Callback(e), undo.err())
+			})
+		}
+	}
+}
+
+/// Change the state of `npages` pages starting at `gfn` through the
+/// MSR protocol, one page at a time.
+///
+/// `f` is called for every page with the new state, and is expected
+/// to `PVALIDATE` it accordingly: it is called before the page is
+/// made shared, to rescind its validation, and after the page is
+/// made private, to validate it.
+///
+/// If any page fails to convert, the pages already converted are
+/// reverted to their original state in reverse order, and a report
+/// of the failure and the rollback is returned.
+pub fn convert_range<T, E, F>(
+	transport: &mut T,
+	gfn: Gfn,
+	npages: u64,
+	op: PageOp,
+	mut f: F,
+) -> Result<(), ConversionReport<E>>
+where
+	T: GhcbMsrTransport + ?Sized,
+	F: FnMut(Gfn, PageOp) -> Result<(), E>,
+{
+	for i in 0..npages {
+		let Err((error, undo_error)) =
+			convert_page(transport, gfn + i, op, &mut f)
+		else {
+			continue;
+		};
+		let mut report = ConversionReport {
+			failed_gfn: gfn + i,
+			error,
+			undo_error,
+			converted: i,
+			rolled_back: 0,
+			rollback_error: None,
+		};
+		for prev in (0..i).rev().map(|j| gfn + j) {
+			let res =
+				convert_page(transport, prev, op.inverse(), &mut f);
+			if let Err((e, _)) = res {
+				report.rollback_error = Some((prev, e));
+				break;
+			}
+			report.rolled_back += 1;
+		}
+		return Err(report);
+	}
+	Ok(())
+}
@@ -0,0 +1,108 @@
+use crate::addr::Gfn;
+use crate::page_state::{PageOp, PageStateReq};
+use crate::GhcbMsrError;
+use core::ops::Range;
+
+/// Tracks the state of the pages in a range of GFNs, to catch
+/// redundant conversions (e.g. sharing an already shared page) before
+/// they reach the hypervisor.
+///
+/// The state is kept in a caller-supplied bitmap, one bit per page,
+/// so the tracker covers `64 * bitmap.len()` pages starting at the
+/// base GFN.
+#[derive(Debug)]
+pub struct PageTracker<'a> {
+	base: Gfn,
+	bitmap: &'a mut [u64],
+}
+
+impl<'a> PageTracker<'a> {
+	/// A tracker with all pages starting out private.
+	pub fn new(base: Gfn, bitmap: &'a mut [u64]) -> Self {
+		bitmap.fill(0);
+		Self { base, bitmap }
+	}
+
+	/// The range of GFNs covered by the tracker.
+	pub fn gfns(&self) -> Range<Gfn> {
+		self.base..self.base + 64 * self.bitmap.len() as u64
+	}
+
+	fn bit(&self, gfn: Gfn) -> Result<(usize, u64), GhcbMsrError> {
+		if !self.gfns().contains(&gfn) {
+			return Err(GhcbMsrError::UntrackedGfn(gfn.as_u64()));
+		}
+		let idx = gfn.as_u64() - self.base.as_u64();
+		Ok(((idx / 64) as usize, 1 << (idx % 64)))
+	}
+
+	/// The current state of a page.
+	pub fn state(&self, gfn: Gfn) -> Result<PageOp, GhcbMsrError> {
+		let (word, mask) = self.bit(gfn)?;
+		if self.bitmap[word] & mask != 0 {
+			Ok(PageOp::Shared)
+		} else {
+			Ok(PageOp::Private)
+		}
+	}
+
+	/// Build a request to change the state of a page, failing if the
+	/// page is not tracked or is already in the requested state.
+	///
+	/// The tracker is not updated until the change is confirmed with
+	/// [`Self::record()`].
+	pub fn request(
+		&self,
+		gfn: Gfn,
+		op: PageOp,
+	) -> Result<PageStateReq, GhcbMsrError> {
+		if self.state(gfn)? == op {
+			return Err(GhcbMsrError::RedundantConversion(
+				gfn.as_u64(),
+			));
+		}
+		PageStateReq::try_new(gfn, op)
+	}
+
+	/// Record a successful state change of a page.
+	pub fn record(
+		&mut self,
+		gfn: Gfn,
+		op: PageOp,
+	) -> Result<(), GhcbMsrError> {
+		let (word, mask) = self.bit(gfn)?;
+		match op {
+			PageOp::Shared => self.bitmap[word] |= mask,
+			PageOp::Private => self.bitmap[word] &= !mask,
+		}
+		Ok(())
+	}
+
+	/// Iterate over the maximal runs of pages in the same state.
+	pub fn regions(&self) -> PageRegions<'_> {
+		PageRegions {
+			tracker: self,
+			gfn: self.base,
+		}
+	}
+}
+
+/// Iterator over the regions of a [`PageTracker`], yielding each
+/// range of GFNs along with its state.
+#[derive(Debug)]
+pub struct PageRegions<'a> {
+	tracker: &'a PageTracker<'a>,
+	gfn: Gfn,
+}
+
+impl Iterator for PageRegions<'_> {
+	type Item = (Range<Gfn>, PageOp);
+	fn next(&mut self) -> Option<Self::Item> {
+		let start = self.gfn;
+		let state = self.tracker.state(start).ok()?;
+		while self.tracker.state(self.gfn) == Ok(state) {
+			self.gfn += 1;
+		}
+		Some((start..self.gfn, state))
+	}
+}
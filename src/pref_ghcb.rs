@@ -1,10 +1,12 @@
+use crate::addr::Gfn;
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
 
 /// A request from the guest asking the hypervisor for a preferred GPA
 /// to use for the GHCB.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct PrefGhcbGpaReq {
 	data: u64,
 }
@@ -15,6 +17,21 @@ impl PrefGhcbGpaReq {
 	}
 }
 
+impl TryFrom<u64> for PrefGhcbGpaReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::PREF_GHCB_GPA_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data != 0 {
+			return Err(GhcbMsrError::InvalidData);
+		}
+		Ok(Self::new())
+	}
+}
+
 impl GhcbMsrRequest for PrefGhcbGpaReq {
 	type Resp = PrefGhcbGpaResp;
 	fn data(&self) -> u64 {
@@ -27,9 +44,16 @@ impl GhcbMsrRequest for PrefGhcbGpaReq {
 
 /// A response from the hypervisor indicating the preferred GFN for
 /// the GHCB (GPA = GFN << 12).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PrefGhcbGpaResp {
 	/// The preferred GFN.
-	pub gfn: u64,
+	pub gfn: Gfn,
+}
+
+impl PrefGhcbGpaResp {
+	pub const fn new(gfn: Gfn) -> Self {
+		Self { gfn }
+	}
 }
 
 impl TryFrom<u64> for PrefGhcbGpaResp {
@@ -40,8 +64,17 @@ impl TryFrom<u64> for PrefGhcbGpaResp {
 		if info != GhcbMsrInfo::PREF_GHCB_GPA_RESP {
 			return Err(GhcbMsrError::MismatchedInfo);
 		}
-		Ok(Self { gfn: data })
+		Ok(Self::new(Gfn::new(data)))
 	}
 }
 
 impl GhcbMsrResp for PrefGhcbGpaResp {}
+
+impl GhcbMsrRespEncode for PrefGhcbGpaResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::PREF_GHCB_GPA_RESP
+	}
+	fn data(&self) -> u64 {
+		self.gfn.as_u64()
+	}
+}
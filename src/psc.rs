@@ -0,0 +1,460 @@
+use crate::addr::{Gfn, Gpa};
+use crate::ghcb::{
+	Ghcb, GhcbError, GhcbRequest, SHARED_BUFFER_OFFSET,
+	SHARED_BUFFER_SIZE,
+};
+use crate::nae::SwExitCode;
+use crate::page_state::PageOp;
+use crate::transport::{exchange_ghcb, GhcbTransport};
+
+/// Maximum number of entries in a [`PscBuffer`], limited by the size
+/// of the shared buffer.
+pub const PSC_MAX_ENTRIES: usize = 253;
+
+const PSC_HEADER_SIZE: usize = 8;
+const PSC_ENTRY_SIZE: usize = 8;
+const PSC_GFN_BITS: u32 = 40;
+
+/// `SW_EXITINFO2` value signaling an invalid PSC header.
+pub const PSC_ERROR_INVALID_HEADER: u64 = (1 << 32) | 1;
+
+/// `SW_EXITINFO2` value signaling an invalid PSC entry.
+pub const PSC_ERROR_INVALID_ENTRY: u64 = (1 << 32) | 2;
+
+/// Operation performed by a [`PscEntry`]. Extends [`PageOp`] with
+/// operations only available through the GHCB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PscOp {
+	Private = 1,
+	Shared = 2,
+	/// Split a 2 MiB private page into 4 KiB pages.
+	Psmash = 3,
+	/// Merge 4 KiB private pages into a 2 MiB page.
+	Unsmash = 4,
+}
+
+impl PscOp {
+	/// The resulting page state, if the operation changes it.
+	pub const fn page_op(&self) -> Option<PageOp> {
+		match self {
+			Self::Private => Some(PageOp::Private),
+			Self::Shared => Some(PageOp::Shared),
+			Self::Psmash | Self::Unsmash => None,
+		}
+	}
+}
+
+impl From<PageOp> for PscOp {
+	fn from(op: PageOp) -> Self {
+		match op {
+			PageOp::Private => Self::Private,
+			PageOp::Shared => Self::Shared,
+		}
+	}
+}
+
+impl TryFrom<u8> for PscOp {
+	type Error = GhcbError;
+	fn try_from(val: u8) -> Result<Self, Self::Error> {
+		match val {
+			v if v == Self::Private as u8 => Ok(Self::Private),
+			v if v == Self::Shared as u8 => Ok(Self::Shared),
+			v if v == Self::Psmash as u8 => Ok(Self::Psmash),
+			v if v == Self::Unsmash as u8 => Ok(Self::Unsmash),
+			_ => Err(GhcbError::InvalidPscBuffer),
+		}
+	}
+}
+
+/// Size of the page covered by a [`PscEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PageSize {
+	Size4K = 0,
+	Size2M = 1,
+}
+
+impl PageSize {
+	/// The number of 4 KiB pages covered.
+	pub const fn pages(&self) -> u64 {
+		match self {
+			Self::Size4K => 1,
+			Self::Size2M => 512,
+		}
+	}
+}
+
+/// An entry of a [`PscBuffer`], describing an operation on a 4 KiB
+/// or 2 MiB page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PscEntry {
+	gfn: Gfn,
+	op: PscOp,
+	size: PageSize,
+	cur_page: u16,
+}
+
+impl PscEntry {
+	/// An entry for the page at `gfn`. 2 MiB pages must be 2 MiB
+	/// aligned. The GFN is not checked, and is truncated to 40 bits
+	/// when encoded. See [`Self::try_new()`].
+	pub const fn new(gfn: Gfn, op: PscOp, size: PageSize) -> Self {
+		Self {
+			gfn,
+			op,
+			size,
+			cur_page: 0,
+		}
+	}
+
+	/// Like [`Self::new()`], but fails if `gfn` does not fit in 40
+	/// bits.
+	pub const fn try_new(
+		gfn: Gfn,
+		op: PscOp,
+		size: PageSize,
+	) -> Result<Self, GhcbError> {
+		if gfn.as_u64() >> PSC_GFN_BITS != 0 {
+			return Err(GhcbError::OutOfRange(gfn.as_u64()));
+		}
+		Ok(Self::new(gfn, op, size))
+	}
+
+	pub const fn gfn(&self) -> Gfn {
+		self.gfn
+	}
+
+	pub const fn op(&self) -> PscOp {
+		self.op
+	}
+
+	pub const fn size(&self) -> PageSize {
+		self.size
+	}
+
+	/// The number of 4 KiB pages of the entry already processed by
+	/// the hypervisor.
+	pub const fn cur_page(&self) -> u16 {
+		self.cur_page
+	}
+
+	pub const fn encode(&self) -> u64 {
+		((self.size as u64) << 56)
+			| ((self.op as u64) << 52)
+			| ((self.gfn.as_u64() & ((1 << PSC_GFN_BITS) - 1)) << 12)
+			| (self.cur_page as u64 & 0xfff)
+	}
+}
+
+impl TryFrom<u64> for PscEntry {
+	type Error = GhcbError;
+	fn try_from(val: u64) -> Result<Self, Self::Error> {
+		if val >> 57 != 0 {
+			return Err(GhcbError::InvalidPscBuffer);
+		}
+		let size = if val & (1 << 56) != 0 {
+			PageSize::Size2M
+		} else {
+			PageSize::Size4K
+		};
+		Ok(Self {
+			gfn: Gfn::new((val >> 12) & 0xffffffffff),
+			op: PscOp::try_from(((val >> 52) & 0xf) as u8)?,
+			size,
+			cur_page: (val & 0xfff) as u16,
+		})
+	}
+}
+
+/// Splits a range of 4 KiB pages into the minimum number of aligned
+/// 2 MiB and 4 KiB pages, yielding the GFN and size of each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangePlanner {
+	gfn: u64,
+	end: u64,
+}
+
+impl RangePlanner {
+	/// A planner for `npages` 4 KiB pages starting at `gfn`.
+	pub const fn new(gfn: Gfn, npages: u64) -> Self {
+		Self {
+			gfn: gfn.as_u64(),
+			end: gfn.as_u64().saturating_add(npages),
+		}
+	}
+}
+
+impl Iterator for RangePlanner {
+	type Item = (Gfn, PageSize);
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.gfn >= self.end {
+			return None;
+		}
+		let large = PageSize::Size2M.pages();
+		let size = if self.gfn.is_multiple_of(large)
+			&& self.end - self.gfn >= large
+		{
+			PageSize::Size2M
+		} else {
+			PageSize::Size4K
+		};
+		let gfn = Gfn::new(self.gfn);
+		self.gfn += size.pages();
+		Some((gfn, size))
+	}
+}
+
+/// The Page State Change structure, placed in the shared buffer for
+/// `SNP_PSC` events. It holds up to [`PSC_MAX_ENTRIES`] entries,
+/// processed by the hypervisor from `cur_entry` to `end_entry`.
+#[derive(Debug, Clone)]
+pub struct PscBuffer {
+	cur_entry: u16,
+	entries: [PscEntry; PSC_MAX_ENTRIES],
+	len: usize,
+}
+
+impl PscBuffer {
+	/// An empty buffer.
+	pub const fn new() -> Self {
+		Self {
+			cur_entry: 0,
+			entries: [PscEntry::new(
+				Gfn::new(0),
+				PscOp::Private,
+				PageSize::Size4K,
+			); PSC_MAX_ENTRIES],
+			len: 0,
+		}
+	}
+
+	/// The entries of the buffer.
+	pub fn entries(&self) -> &[PscEntry] {
+		&self.entries[..self.len]
+	}
+
+	/// The index of the first entry not yet fully processed.
+	pub const fn cur_entry(&self) -> u16 {
+		self.cur_entry
+	}
+
+	/// Whether all entries have been processed.
+	pub const fn is_done(&self) -> bool {
+		self.cur_entry as usize >= self.len
+	}
+
+	pub const fn is_full(&self) -> bool {
+		self.len == PSC_MAX_ENTRIES
+	}
+
+	/// Mark the current entry as fully processed and move on to the
+	/// next one. Used by the hypervisor side.
+	pub fn complete_entry(&mut self) {
+		if let Some(entry) =
+			self.entries[..self.len].get_mut(self.cur_entry as usize)
+		{
+			entry.cur_page = entry.size.pages() as u16;
+			self.cur_entry += 1;
+		}
+	}
+
+	/// Whether the buffer is the result of the hypervisor making
+	/// progress on `prev`, without changing the entries themselves.
+	fn is_progress_of(&self, prev: &Self) -> bool {
+		let same = |(a, b): (&PscEntry, &PscEntry)| {
+			a.gfn == b.gfn && a.op == b.op && a.size == b.size
+		};
+		self.len == prev.len
+			&& self.cur_entry >= prev.cur_entry
+			&& self != prev
+			&& self.entries().iter().zip(prev.entries()).all(same)
+	}
+
+	/// Remove all entries.
+	pub fn clear(&mut self) {
+		self.cur_entry = 0;
+		self.len = 0;
+	}
+
+	/// Add an entry. Fails if the buffer is full, returning the
+	/// entry.
+	pub fn push(&mut self, entry: PscEntry) -> Result<(), PscEntry> {
+		if self.is_full() {
+			return Err(entry);
+		}
+		self.entries[self.len] = entry;
+		self.len += 1;
+		Ok(())
+	}
+
+	/// Add entries for `npages` 4 KiB pages starting at `gfn`, as
+	/// many as fit, using 2 MiB entries where possible (see
+	/// [`RangePlanner`]). Returns the number of 4 KiB pages added.
+	///
+	/// Fails without adding any entry if a page of the range does
+	/// not fit in a [`PscEntry`]. Only page state changes are
+	/// accepted: [`PscOp::Psmash`] and [`PscOp::Unsmash`] apply to
+	/// single 2 MiB pages, and must be pushed with [`Self::push()`].
+	pub fn push_range(
+		&mut self,
+		gfn: Gfn,
+		npages: u64,
+		op: PageOp,
+	) -> Result<u64, GhcbError> {
+		if npages > 0 {
+			let last = gfn.as_u64().saturating_add(npages - 1);
+			if last >> PSC_GFN_BITS != 0 {
+				return Err(GhcbError::OutOfRange(last));
+			}
+		}
+		let mut added = 0;
+		for (gfn, size) in RangePlanner::new(gfn, npages) {
+			let entry = PscEntry::new(gfn, op.into(), size);
+			if self.push(entry).is_err() {
+				break;
+			}
+			added += size.pages();
+		}
+		Ok(added)
+	}
+
+	/// Serialize the buffer into `buf`, which must hold at least
+	/// [`SHARED_BUFFER_SIZE`] bytes.
+	///
+	/// # Panics
+	///
+	/// Panics if `buf` is too small.
+	pub fn write_bytes(&self, buf: &mut [u8]) {
+		let end_entry = (self.len as u16).wrapping_sub(1);
+		buf[0..2].copy_from_slice(&self.cur_entry.to_le_bytes());
+		buf[2..4].copy_from_slice(&end_entry.to_le_bytes());
+		buf[4..8].fill(0);
+		for (i, entry) in self.entries().iter().enumerate() {
+			let off = PSC_HEADER_SIZE + i * PSC_ENTRY_SIZE;
+			buf[off..off + PSC_ENTRY_SIZE]
+				.copy_from_slice(&entry.encode().to_le_bytes());
+		}
+	}
+
+	/// Parse a buffer serialized with [`Self::write_bytes()`].
+	pub fn from_bytes(buf: &[u8]) -> Result<Self, GhcbError> {
+		if buf.len() < SHARED_BUFFER_SIZE {
+			return Err(GhcbError::InvalidLength(buf.len()));
+		}
+		let cur_entry = u16::from_le_bytes([buf[0], buf[1]]);
+		let end_entry = u16::from_le_bytes([buf[2], buf[3]]);
+		let len = end_entry as usize + 1;
+		if len > PSC_MAX_ENTRIES || cur_entry as usize > len {
+			return Err(GhcbError::InvalidPscBuffer);
+		}
+		let mut psc = Self::new();
+		psc.cur_entry = cur_entry;
+		for i in 0..len {
+			let off = PSC_HEADER_SIZE + i * PSC_ENTRY_SIZE;
+			let mut val = [0u8; PSC_ENTRY_SIZE];
+			val.copy_from_slice(&buf[off..off + PSC_ENTRY_SIZE]);
+			let entry = PscEntry::try_from(u64::from_le_bytes(val))?;
+			let _ = psc.push(entry);
+		}
+		Ok(psc)
+	}
+}
+
+// Entries past `len` are leftovers from before a `clear()`, and must
+// not take part in the comparison.
+impl PartialEq for PscBuffer {
+	fn eq(&self, other: &Self) -> bool {
+		self.cur_entry == other.cur_entry
+			&& self.entries() == other.entries()
+	}
+}
+
+impl Eq for PscBuffer {}
+
+impl Default for PscBuffer {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// A request to process the [`PscBuffer`] already placed in the
+/// shared buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PscReq;
+
+impl GhcbRequest for PscReq {
+	type Resp = ();
+	fn exit_code(&self) -> SwExitCode {
+		SwExitCode::SNP_PSC
+	}
+	fn prepare(&self, ghcb: &mut Ghcb, ghcb_gpa: Gpa) {
+		let scratch = ghcb_gpa + SHARED_BUFFER_OFFSET as u64;
+		ghcb.set_sw_scratch(scratch.as_u64());
+	}
+	fn response(&self, ghcb: &Ghcb) -> Result<Self::Resp, GhcbError> {
+		match ghcb.sw_exit_info_2() {
+			0 => Ok(()),
+			info => {
+				let cur_entry = u16::from_le_bytes([
+					ghcb.shared_buffer()[0],
+					ghcb.shared_buffer()[1],
+				]);
+				Err(GhcbError::PageStateChange(cur_entry, info))
+			}
+		}
+	}
+}
+
+/// Have the hypervisor process all pending entries of `psc`,
+/// issuing as many `SNP_PSC` events as needed. The hypervisor may
+/// return after processing only part of the entries, in which case
+/// the request is resumed from `cur_entry`.
+///
+/// `psc` is kept in sync with the progress reported by the
+/// hypervisor, even on failure. If an entry cannot be processed, the
+/// call fails with [`GhcbError::PageStateChange`], holding the index
+/// of the entry.
+pub fn submit<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	psc: &mut PscBuffer,
+) -> Result<(), GhcbError> {
+	psc.write_bytes(transport.ghcb().shared_buffer_mut());
+	while !psc.is_done() {
+		let res = exchange_ghcb(transport, &PscReq);
+		let next =
+			PscBuffer::from_bytes(transport.ghcb().shared_buffer())
+				.ok()
+				.filter(|next| next.is_progress_of(psc));
+		// Keep the progress made before a failure, so that a retry
+		// resumes from the failing entry.
+		let progress = next.is_some();
+		if let Some(next) = next {
+			*psc = next;
+		}
+		res?;
+		if !progress {
+			return Err(GhcbError::InvalidPscBuffer);
+		}
+	}
+	Ok(())
+}
+
+/// Change the state of `npages` 4 KiB pages starting at `gfn`,
+/// packing as many pages as possible into each [`PscBuffer`].
+pub fn change_range<T: GhcbTransport + ?Sized>(
+	transport: &mut T,
+	mut gfn: Gfn,
+	mut npages: u64,
+	op: PageOp,
+) -> Result<(), GhcbError> {
+	let mut psc = PscBuffer::new();
+	while npages > 0 {
+		psc.clear();
+		let added = psc.push_range(gfn, npages, op)?;
+		submit(transport, &mut psc)?;
+		gfn += added;
+		npages -= added;
+	}
+	Ok(())
+}
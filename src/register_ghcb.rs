@@ -1,5 +1,7 @@
+use crate::addr::Gfn;
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
 
 /// A request from the guest to indicate to the hypervisor the GFN
@@ -7,19 +9,47 @@ use crate::{
 /// `VMGEXIT`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RegisterGhcbReq {
-	data: u64,
+	gfn: Gfn,
 }
 
 impl RegisterGhcbReq {
-	pub const fn new(gfn: u64) -> Self {
-		Self { data: gfn }
+	/// The GFN is not checked, and is truncated to 52 bits when
+	/// encoded. See [`Self::try_new()`].
+	pub const fn new(gfn: Gfn) -> Self {
+		Self { gfn }
+	}
+
+	/// Like [`Self::new()`], but fails if `gfn` does not fit in 52
+	/// bits.
+	pub const fn try_new(gfn: Gfn) -> Result<Self, GhcbMsrError> {
+		if gfn.as_u64() >> 52 != 0 {
+			return Err(GhcbMsrError::OutOfRange(gfn.as_u64()));
+		}
+		Ok(Self::new(gfn))
+	}
+
+	/// The GFN to be registered.
+	pub const fn gfn(&self) -> Gfn {
+		self.gfn
+	}
+}
+
+impl TryFrom<u64> for RegisterGhcbReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::REG_GHCB_GPA_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		Ok(Self::new(Gfn::new(data)))
 	}
 }
 
 impl GhcbMsrRequest for RegisterGhcbReq {
 	type Resp = RegisterGhcbResp;
 	fn data(&self) -> u64 {
-		self.data
+		self.gfn.as_u64()
 	}
 	fn info(&self) -> GhcbMsrInfo {
 		GhcbMsrInfo::REG_GHCB_GPA_REQ
@@ -29,8 +59,10 @@ impl GhcbMsrRequest for RegisterGhcbReq {
 		resp: u64,
 	) -> Result<Self::Resp, GhcbMsrError> {
 		let resp = Self::Resp::try_from(resp)?;
-		if resp.gfn != self.data {
-			return Err(GhcbMsrError::MismatchedData(resp.gfn));
+		if resp.gfn != self.gfn {
+			return Err(GhcbMsrError::MismatchedData(
+				resp.gfn.as_u64(),
+			));
 		}
 		Ok(resp)
 	}
@@ -38,9 +70,17 @@ impl GhcbMsrRequest for RegisterGhcbReq {
 
 /// A response from the hypervisor after a request to register a GHCB
 /// GFN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RegisterGhcbResp {
 	/// The registered GHCB GFN.
-	pub gfn: u64,
+	pub gfn: Gfn,
+}
+
+impl RegisterGhcbResp {
+	/// A response confirming the registration of `gfn`.
+	pub const fn for_gfn(gfn: Gfn) -> Self {
+		Self { gfn }
+	}
 }
 
 impl TryFrom<u64> for RegisterGhcbResp {
@@ -51,8 +91,17 @@ impl TryFrom<u64> for RegisterGhcbResp {
 		if info != GhcbMsrInfo::REG_GHCB_GPA_RESP {
 			return Err(GhcbMsrError::MismatchedInfo);
 		}
-		Ok(Self { gfn: data })
+		Ok(Self::for_gfn(Gfn::new(data)))
 	}
 }
 
 impl GhcbMsrResp for RegisterGhcbResp {}
+
+impl GhcbMsrRespEncode for RegisterGhcbResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::REG_GHCB_GPA_RESP
+	}
+	fn data(&self) -> u64 {
+		self.gfn.as_u64()
+	}
+}
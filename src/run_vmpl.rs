@@ -1,40 +1,93 @@
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
 
 /// A request to the hypervisor to run the vCPU using the VMSA
 /// associated with the request VMPL level.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RunVmplReq {
-	data: u64,
+	vmpl: u8,
 }
 
 impl RunVmplReq {
+	/// The VMPL is not checked. See [`Self::try_new()`].
 	pub const fn new(vmpl: u8) -> Self {
-		Self {
-			data: (vmpl as u64) << 20,
+		Self { vmpl }
+	}
+
+	/// Like [`Self::new()`], but fails if `vmpl` is not a valid VMPL
+	/// (0 to 3).
+	pub const fn try_new(vmpl: u8) -> Result<Self, GhcbMsrError> {
+		if vmpl > 3 {
+			return Err(GhcbMsrError::OutOfRange(vmpl as u64));
+		}
+		Ok(Self::new(vmpl))
+	}
+
+	/// The requested VMPL.
+	pub const fn vmpl(&self) -> u8 {
+		self.vmpl
+	}
+}
+
+impl TryFrom<u64> for RunVmplReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::RUN_VMPL_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data & 0xfffff != 0 || data >> 28 != 0 {
+			return Err(GhcbMsrError::InvalidData);
 		}
+		Ok(Self::new((data >> 20) as u8))
 	}
 }
 
 impl GhcbMsrRequest for RunVmplReq {
 	type Resp = RunVmplResp;
 	fn data(&self) -> u64 {
-		self.data
+		(self.vmpl as u64) << 20
 	}
 	fn info(&self) -> GhcbMsrInfo {
 		GhcbMsrInfo::RUN_VMPL_REQ
 	}
+	fn response(
+		&self,
+		resp: u64,
+	) -> Result<Self::Resp, GhcbMsrError> {
+		let resp = RunVmplResp::try_from(resp)?;
+		match resp.error_code {
+			0 => Ok(resp),
+			code => Err(GhcbMsrError::Hypervisor(code)),
+		}
+	}
 }
 
 /// A response from the hypervisor after requesting running at a
 /// specified VPML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct RunVmplResp {
 	/// Non-zero if the hypervisor was unable to run the vCPU at the
-	/// requested VPML.
+	/// requested VPML. The code is hypervisor-specific.
+	/// [`RunVmplReq::response()`] turns it into an error.
 	pub error_code: u32,
 }
 
+impl RunVmplResp {
+	/// A response indicating success.
+	pub const fn new() -> Self {
+		Self::with_error(0)
+	}
+
+	/// A response with the given error code.
+	pub const fn with_error(error_code: u32) -> Self {
+		Self { error_code }
+	}
+}
+
 impl TryFrom<u64> for RunVmplResp {
 	type Error = GhcbMsrError;
 	fn try_from(resp: u64) -> Result<Self, Self::Error> {
@@ -52,3 +105,12 @@ impl TryFrom<u64> for RunVmplResp {
 }
 
 impl GhcbMsrResp for RunVmplResp {}
+
+impl GhcbMsrRespEncode for RunVmplResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::RUN_VMPL_RESP
+	}
+	fn data(&self) -> u64 {
+		(self.error_code as u64) << 20
+	}
+}
@@ -0,0 +1,171 @@
+use crate::addr::{AddrSpace, Gfn, Gpa};
+use crate::register_ghcb::RegisterGhcbReq;
+use crate::sev_info::{NegotiatedProtocol, SevInfoReq};
+use crate::termination::TerminationReq;
+use crate::transport::{exchange, GhcbMsrTransport};
+use crate::{GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest};
+
+/// Session state before the GHCB protocol version is negotiated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unnegotiated;
+
+/// Session state after the GHCB protocol version is negotiated, but
+/// before a GHCB is registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+	proto: NegotiatedProtocol,
+}
+
+/// Session state after a GHCB is registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registered {
+	proto: NegotiatedProtocol,
+	gfn: Gfn,
+}
+
+mod sealed {
+	pub trait Sealed {}
+	impl Sealed for super::Negotiated {}
+	impl Sealed for super::Registered {}
+}
+
+/// Session states in which a protocol version has been negotiated.
+pub trait Active: sealed::Sealed {
+	/// The negotiated protocol.
+	fn protocol(&self) -> NegotiatedProtocol;
+}
+
+impl Active for Negotiated {
+	fn protocol(&self) -> NegotiatedProtocol {
+		self.proto
+	}
+}
+
+impl Active for Registered {
+	fn protocol(&self) -> NegotiatedProtocol {
+		self.proto
+	}
+}
+
+/// A GHCB session over a transport, enforcing the order in which
+/// the protocol must be used: the protocol version is negotiated
+/// first, then the GHCB is registered.
+///
+/// Each state only exposes the operations valid in it, so that
+/// calling them out of order is a compile-time error.
+#[derive(Debug)]
+pub struct GhcbSession<T, S> {
+	transport: T,
+	state: S,
+}
+
+impl<T: GhcbMsrTransport> GhcbSession<T, Unnegotiated> {
+	pub const fn new(transport: T) -> Self {
+		Self {
+			transport,
+			state: Unnegotiated,
+		}
+	}
+
+	/// Negotiate the protocol version with the hypervisor, given
+	/// the guest's supported range `min_ver..=max_ver`.
+	///
+	/// If there is no common version, the guest requests
+	/// termination, so this only returns an error if the
+	/// hypervisor does not comply or misbehaves.
+	pub fn negotiate(
+		mut self,
+		min_ver: u16,
+		max_ver: u16,
+	) -> Result<GhcbSession<T, Negotiated>, GhcbMsrError> {
+		let info = exchange(&mut self.transport, &SevInfoReq::new())?;
+		match info.negotiate(min_ver, max_ver) {
+			Ok(proto) => Ok(GhcbSession {
+				transport: self.transport,
+				state: Negotiated { proto },
+			}),
+			Err(term) => {
+				exchange(&mut self.transport, &term)?;
+				Err(GhcbMsrError::ShouldNotReturn)
+			}
+		}
+	}
+}
+
+impl<T: GhcbMsrTransport> GhcbSession<T, Negotiated> {
+	/// Register the GHCB at the given GFN and leave its GPA in the
+	/// GHCB MSR.
+	///
+	/// The registration request only exists in protocol version 2;
+	/// with version 1 the GPA is simply written to the MSR. Fails
+	/// without contacting the hypervisor if the GPA has the C-bit
+	/// set.
+	pub fn register(
+		mut self,
+		gfn: Gfn,
+	) -> Result<GhcbSession<T, Registered>, GhcbMsrError> {
+		let proto = self.state.proto;
+		let space = AddrSpace::new(64, proto.enc_bit_no());
+		let gfn = space.check_gfn(gfn)?;
+		let gpa = Gpa::try_from(gfn)?;
+		if proto.supports(GhcbMsrInfo::REG_GHCB_GPA_REQ) {
+			let req = RegisterGhcbReq::try_new(gfn)?;
+			exchange(&mut self.transport, &req)?;
+		}
+		self.transport.write_msr(gpa.as_u64());
+		Ok(GhcbSession {
+			transport: self.transport,
+			state: Registered { proto, gfn },
+		})
+	}
+}
+
+impl<T: GhcbMsrTransport> GhcbSession<T, Registered> {
+	/// The GFN of the registered GHCB.
+	pub fn gfn(&self) -> Gfn {
+		self.state.gfn
+	}
+}
+
+impl<T: GhcbMsrTransport, S: Active> GhcbSession<T, S> {
+	/// The negotiated protocol.
+	pub fn protocol(&self) -> NegotiatedProtocol {
+		self.state.protocol()
+	}
+
+	/// Perform a request, checking first that it is available in
+	/// the negotiated protocol version.
+	pub fn exchange<R: GhcbMsrRequest>(
+		&mut self,
+		req: &R,
+	) -> Result<R::Resp, GhcbMsrError> {
+		let info = req.info();
+		if !self.protocol().supports(info) {
+			return Err(GhcbMsrError::UnsupportedVersion(
+				info.min_version(),
+			));
+		}
+		exchange(&mut self.transport, req)
+	}
+}
+
+impl<T: GhcbMsrTransport, S> GhcbSession<T, S> {
+	/// Request termination of the guest. This only returns if the
+	/// hypervisor does not comply.
+	pub fn terminate(
+		mut self,
+		code_set: u8,
+		reason: u8,
+	) -> GhcbMsrError {
+		let req = TerminationReq::new(code_set, reason);
+		match exchange(&mut self.transport, &req) {
+			Ok(_) => GhcbMsrError::ShouldNotReturn,
+			Err(e) => e,
+		}
+	}
+
+	/// Give up the session, returning the underlying transport.
+	pub fn into_transport(self) -> T {
+		self.transport
+	}
+}
@@ -1,10 +1,12 @@
+use crate::termination::{TerminationReason, TerminationReq};
 use crate::{
-	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest,
+	GhcbMsrResp, GhcbMsrRespEncode,
 };
 
 /// A request for the hypervisor to provide SEV information needed to
 /// perform protocol negotiation.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct SevInfoReq {
 	data: u64,
 }
@@ -15,6 +17,21 @@ impl SevInfoReq {
 	}
 }
 
+impl TryFrom<u64> for SevInfoReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::SEV_INFO_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data != 0 {
+			return Err(GhcbMsrError::InvalidData);
+		}
+		Ok(Self::new())
+	}
+}
+
 impl GhcbMsrRequest for SevInfoReq {
 	type Resp = SevInfoResp;
 	fn data(&self) -> u64 {
@@ -37,6 +54,47 @@ pub struct SevInfoResp {
 	pub enc_bit_no: u8,
 }
 
+impl SevInfoResp {
+	pub const fn new(
+		min_ver: u16,
+		max_ver: u16,
+		enc_bit_no: u8,
+	) -> Self {
+		Self {
+			max_ver,
+			min_ver,
+			enc_bit_no,
+		}
+	}
+
+	/// Select the highest GHCB protocol version supported by both
+	/// the hypervisor and the guest, whose supported range is
+	/// `min_ver..=max_ver`.
+	///
+	/// If there is no common version, the guest must terminate
+	/// by sending the returned [`TerminationReq`].
+	pub const fn negotiate(
+		&self,
+		min_ver: u16,
+		max_ver: u16,
+	) -> Result<NegotiatedProtocol, TerminationReq> {
+		let version = if self.max_ver < max_ver {
+			self.max_ver
+		} else {
+			max_ver
+		};
+		if version < min_ver || version < self.min_ver {
+			return Err(TerminationReq::from_reason(
+				TerminationReason::GhcbProtRangeNotSupported,
+			));
+		}
+		Ok(NegotiatedProtocol {
+			version,
+			enc_bit_no: self.enc_bit_no,
+		})
+	}
+}
+
 impl TryFrom<u64> for SevInfoResp {
 	type Error = GhcbMsrError;
 	fn try_from(resp: u64) -> Result<Self, Self::Error> {
@@ -58,3 +116,56 @@ impl TryFrom<u64> for SevInfoResp {
 }
 
 impl GhcbMsrResp for SevInfoResp {}
+
+impl GhcbMsrRespEncode for SevInfoResp {
+	fn info(&self) -> GhcbMsrInfo {
+		GhcbMsrInfo::SEV_INFO_RESP
+	}
+	fn data(&self) -> u64 {
+		((self.max_ver as u64) << 36)
+			| ((self.min_ver as u64) << 20)
+			| ((self.enc_bit_no as u64) << 12)
+	}
+}
+
+/// The outcome of a successful protocol negotiation, see
+/// [`SevInfoResp::negotiate()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegotiatedProtocol {
+	version: u16,
+	enc_bit_no: u8,
+}
+
+impl NegotiatedProtocol {
+	/// The GHCB protocol version in use.
+	pub const fn version(&self) -> u16 {
+		self.version
+	}
+
+	/// The position of the encryption bit reported by the
+	/// hypervisor.
+	pub const fn enc_bit_no(&self) -> u8 {
+		self.enc_bit_no
+	}
+
+	/// Whether the given message is available in the negotiated
+	/// protocol version.
+	pub const fn supports(&self, info: GhcbMsrInfo) -> bool {
+		info.min_version() <= self.version
+	}
+
+	/// Check that a request is available in the negotiated protocol
+	/// version, returning it back if so.
+	pub fn check<R: GhcbMsrRequest>(
+		&self,
+		req: R,
+	) -> Result<R, GhcbMsrError> {
+		let info = req.info();
+		if !self.supports(info) {
+			return Err(GhcbMsrError::UnsupportedVersion(
+				info.min_version(),
+			));
+		}
+		Ok(req)
+	}
+}
@@ -1,40 +1,114 @@
-use crate::{GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp};
+use crate::{
+	parse_msr, GhcbMsrError, GhcbMsrInfo, GhcbMsrRequest, GhcbMsrResp,
+};
 
 /// The reason for a [`TerminationReq`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum TerminationReason {
 	/// General termination request.
-	GeneralTermination = 1,
+	GeneralTermination = 0,
 	/// SEV-ES/GHCB Protocol range is not supported.
-	GhcbProtRangeNotSupported = 2,
+	GhcbProtRangeNotSupported = 1,
 	/// SEV-SNP features not supported
-	SevSnpNotSupported = 3,
+	SevSnpNotSupported = 2,
 }
 
-/// A request from the guest to be terminated.
+impl TryFrom<u8> for TerminationReason {
+	type Error = GhcbMsrError;
+	fn try_from(val: u8) -> Result<Self, Self::Error> {
+		match val {
+			v if v == Self::GeneralTermination as u8 => {
+				Ok(Self::GeneralTermination)
+			}
+			v if v == Self::GhcbProtRangeNotSupported as u8 => {
+				Ok(Self::GhcbProtRangeNotSupported)
+			}
+			v if v == Self::SevSnpNotSupported as u8 => {
+				Ok(Self::SevSnpNotSupported)
+			}
+			_ => Err(GhcbMsrError::InvalidData),
+		}
+	}
+}
+
+/// A request from the guest to be terminated. The reason is a raw
+/// code, whose meaning depends on the code set: set 0 is defined by
+/// the GHCB specification (see [`TerminationReason`]), the others
+/// are vendor-specific.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TerminationReq {
-	data: u64,
+	code_set: u8,
+	reason: u8,
 }
 
 impl TerminationReq {
-	pub const fn new(
+	/// The code set is not checked, and overwrites the reason when
+	/// encoded if wider than 4 bits. See [`Self::try_new()`].
+	pub const fn new(code_set: u8, reason: u8) -> Self {
+		Self { code_set, reason }
+	}
+
+	/// Like [`Self::new()`], but fails if `code_set` does not fit in
+	/// 4 bits.
+	pub const fn try_new(
 		code_set: u8,
-		reason: TerminationReason,
-	) -> Self {
-		let code_set = code_set as u64;
-		let reason = reason as u64;
-		Self {
-			data: (reason << 4) | code_set,
+		reason: u8,
+	) -> Result<Self, GhcbMsrError> {
+		if code_set >> 4 != 0 {
+			return Err(GhcbMsrError::OutOfRange(code_set as u64));
+		}
+		Ok(Self::new(code_set, reason))
+	}
+
+	/// A request with a reason from code set 0.
+	pub const fn from_reason(reason: TerminationReason) -> Self {
+		Self::new(0, reason as u8)
+	}
+
+	/// The reason code set.
+	pub const fn code_set(&self) -> u8 {
+		self.code_set
+	}
+
+	/// The raw termination reason.
+	pub const fn reason(&self) -> u8 {
+		self.reason
+	}
+
+	/// The termination reason, if the request uses code set 0 and
+	/// the reason is known.
+	pub fn ghcb_reason(&self) -> Option<TerminationReason> {
+		if self.code_set != 0 {
+			return None;
+		}
+		TerminationReason::try_from(self.reason).ok()
+	}
+}
+
+impl TryFrom<u64> for TerminationReq {
+	type Error = GhcbMsrError;
+	fn try_from(req: u64) -> Result<Self, Self::Error> {
+		let (info, data) = parse_msr(req);
+		let info = GhcbMsrInfo::try_from(info)?;
+		if info != GhcbMsrInfo::TERM_REQ {
+			return Err(GhcbMsrError::MismatchedInfo);
+		}
+		if data >> 12 != 0 {
+			return Err(GhcbMsrError::InvalidData);
 		}
+		let code_set = (data & 0xf) as u8;
+		let reason = ((data >> 4) & 0xff) as u8;
+		Ok(Self::new(code_set, reason))
 	}
 }
 
 impl GhcbMsrRequest for TerminationReq {
 	type Resp = TerminationResp;
 	fn data(&self) -> u64 {
-		self.data
+		let code_set = self.code_set as u64;
+		let reason = self.reason as u64;
+		(reason << 4) | code_set
 	}
 	fn info(&self) -> GhcbMsrInfo {
 		GhcbMsrInfo::TERM_REQ
@@ -0,0 +1,109 @@
+use crate::addr::Gpa;
+use crate::exit_info::check_exit_info;
+use crate::ghcb::{Ghcb, GhcbError, GhcbField, GhcbRequest};
+use crate::{GhcbMsrError, GhcbMsrRequest};
+
+/// Low-level access to the GHCB MSR and the `VMGEXIT` instruction.
+///
+/// This is the only part of the protocol that requires privileged
+/// (and `unsafe`) instructions, so it is left to the user to
+/// implement.
+pub trait GhcbMsrTransport {
+	/// Write a value to the GHCB MSR.
+	fn write_msr(&mut self, val: u64);
+	/// Read the current value of the GHCB MSR.
+	fn read_msr(&mut self) -> u64;
+	/// Exit to the hypervisor.
+	fn vmgexit(&mut self);
+}
+
+/// Access to the GHCB page of the current vCPU, in addition to the
+/// GHCB MSR, needed to perform non-automatic exit events.
+///
+/// The GHCB must have been registered and have its protocol version
+/// set by the user.
+pub trait GhcbTransport: GhcbMsrTransport {
+	/// The GHCB page.
+	fn ghcb(&mut self) -> &mut Ghcb;
+	/// The GPA of the GHCB page.
+	fn ghcb_gpa(&self) -> Gpa;
+}
+
+impl<T: GhcbMsrTransport + ?Sized> GhcbMsrTransport for &mut T {
+	fn write_msr(&mut self, val: u64) {
+		(**self).write_msr(val)
+	}
+	fn read_msr(&mut self) -> u64 {
+		(**self).read_msr()
+	}
+	fn vmgexit(&mut self) {
+		(**self).vmgexit()
+	}
+}
+
+impl<T: GhcbTransport + ?Sized> GhcbTransport for &mut T {
+	fn ghcb(&mut self) -> &mut Ghcb {
+		(**self).ghcb()
+	}
+	fn ghcb_gpa(&self) -> Gpa {
+		(**self).ghcb_gpa()
+	}
+}
+
+/// Perform a full MSR protocol round-trip: write the request to the
+/// GHCB MSR, exit to the hypervisor, and parse the response.
+///
+/// The value held by the GHCB MSR before the call (usually the GPA
+/// of the registered GHCB) is restored before returning, regardless
+/// of whether the response is valid.
+pub fn exchange<T, R>(
+	transport: &mut T,
+	req: &R,
+) -> Result<R::Resp, GhcbMsrError>
+where
+	T: GhcbMsrTransport + ?Sized,
+	R: GhcbMsrRequest,
+{
+	let prev = transport.read_msr();
+	transport.write_msr(req.msr());
+	transport.vmgexit();
+	let resp = transport.read_msr();
+	transport.write_msr(prev);
+	req.response(resp)
+}
+
+/// Perform a non-automatic exit event through the GHCB page: clear
+/// the page, fill in the request, write the GHCB GPA to the GHCB MSR,
+/// exit to the hypervisor, and parse the response.
+///
+/// If the request uses `SW_SCRATCH`, the hypervisor is not allowed
+/// to change it.
+pub fn exchange_ghcb<T, R>(
+	transport: &mut T,
+	req: &R,
+) -> Result<R::Resp, GhcbError>
+where
+	T: GhcbTransport + ?Sized,
+	R: GhcbRequest,
+{
+	let gpa = transport.ghcb_gpa();
+	let ghcb = transport.ghcb();
+	ghcb.clear();
+	ghcb.set_sw_exit_code(req.exit_code() as u64);
+	ghcb.set_sw_exit_info_1(req.exit_info_1());
+	ghcb.set_sw_exit_info_2(req.exit_info_2());
+	req.prepare(ghcb, gpa);
+	let scratch = ghcb.is_valid(GhcbField::SwScratch);
+	let scratch = scratch.then(|| ghcb.sw_scratch());
+	transport.write_msr(gpa.as_u64());
+	transport.vmgexit();
+
+	let ghcb = transport.ghcb();
+	check_exit_info(ghcb.sw_exit_info_1(), ghcb.sw_exit_info_2())?;
+	if let Some(scratch) = scratch {
+		if ghcb.sw_scratch() != scratch {
+			return Err(GhcbError::ScratchChanged(ghcb.sw_scratch()));
+		}
+	}
+	req.response(ghcb)
+}